@@ -1,8 +1,169 @@
+// SPIR-V variants of the mandelbrot shader generated from its WGSL source
+const MANDELBROT_WGSL: &str = "src/shaders/mandelbrot.wgsl";
+
+// the instruction helpers and the specialization constant IDs of the backends, which edit the modules at runtime
+#[allow(dead_code)]
+#[path = "src/shaders/spirv.rs"]
+mod shader_spirv;
+
+use shader_spirv::{instruction, opcode};
+
+// capabilities the shaders given with --shader are validated with as well
+#[path = "src/shaders/validation.rs"]
+mod validation;
+
+// specialization constant IDs the template constants get
+const SPEC_CONSTANTS: [(&str, u32); 2] = [
+    ("PIXELS_PER_INVOCATION", shader_spirv::PIXELS_PER_INVOCATION_ID),
+    ("SAMPLE_COUNT", shader_spirv::SAMPLE_COUNT_ID)
+];
+
+
+// nul-terminated literal string operand
+fn string_operand(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).take_while(|b| *b != 0).collect();
+    String::from_utf8(bytes).unwrap()
+}
+
+
+fn string_words(s: &str) -> Vec<u32> {
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.resize(s.len() / 4 * 4 + 4, 0);
+    bytes.chunks_exact(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect()
+}
+
+
+// module split into instructions, which are easier to insert
+struct Module {
+    header: Vec<u32>,
+    instructions: Vec<Vec<u32>>
+}
+
+
+impl Module {
+    fn parse(words: &[u32]) -> Self {
+        Module { header: words[..5].to_vec(), instructions: shader_spirv::instructions(words).map(<[u32]>::to_vec).collect() }
+    }
+
+    fn assemble(&self) -> Vec<u32> {
+        self.header.iter().chain(self.instructions.iter().flatten()).copied().collect()
+    }
+
+    fn new_id(&mut self) -> u32 {
+        self.header[3] += 1;
+        self.header[3] - 1
+    }
+
+    // naga may write the module differently than these edits expect, the panic tells what is missing
+    fn position(&self, description: &str, predicate: impl Fn(&[u32]) -> bool) -> usize {
+        self.instructions.iter().position(|i| predicate(i))
+            .unwrap_or_else(|| panic!("Generated SPIR-V module has no {description}"))
+    }
+
+    fn find(&self, description: &str, predicate: impl Fn(&[u32]) -> bool) -> &[u32] {
+        &self.instructions[self.position(description, predicate)]
+    }
+
+    fn insert_annotation(&mut self, annotation: Vec<u32>) {
+        let last = self.instructions.iter()
+            .rposition(|i| (spirv::Op::Decorate as u32..=spirv::Op::GroupMemberDecorate as u32).contains(&opcode(i)))
+            .expect("Generated SPIR-V module has no annotations");
+        self.instructions.insert(last + 1, annotation);
+    }
+}
+
+
+// naga writes template constants as plain ones, they are made specializable by their debug names
+fn make_spec_constants(module: &mut Module) {
+    for (name, spec_id) in SPEC_CONSTANTS {
+        let id = module.find(&format!("debug name {name}"), |i| opcode(i) == spirv::Op::Name as u32 && string_operand(&i[2..]) == name)[1];
+        let constant = module.position(&format!("constant {name}"), |i| opcode(i) == spirv::Op::Constant as u32 && i[2] == id);
+        module.instructions[constant][0] = 4 << 16 | spirv::Op::SpecConstant as u32;
+        module.insert_annotation(instruction(spirv::Op::Decorate, &[id, spirv::Decoration::SpecId as u32, spec_id]));
+    }
+}
+
+
+// WGSL has no 8-bit integers, the array of pixels is turned into a byte array and the values stored into it are
+// converted to bytes
+fn narrow_pixels_to_u8(module: &mut Module) {
+    let uint_position = module.position("32-bit unsigned integer type", |i| opcode(i) == spirv::Op::TypeInt as u32 && i[2..] == [32, 0]);
+    let uint = module.instructions[uint_position][1];
+    let uchar = module.new_id();
+    module.instructions.insert(uint_position + 1, instruction(spirv::Op::TypeInt, &[uchar, 8, 0]));
+
+    // v_pixels is the only runtime-sized array and the only storage buffer
+    let array = module.position("runtime array of pixels", |i| opcode(i) == spirv::Op::TypeRuntimeArray as u32 && i[2] == uint);
+    module.instructions[array][2] = uchar;
+    let array = module.instructions[array][1];
+    let stride = module.position("array stride of the pixels", |i| opcode(i) == spirv::Op::Decorate as u32 && i[1] == array &&
+        i[2] == spirv::Decoration::ArrayStride as u32);
+    module.instructions[stride][3] = 1;
+    let pointer = module.position("storage buffer pointer to the pixels", |i| opcode(i) == spirv::Op::TypePointer as u32 &&
+        i[2] == spirv::StorageClass::StorageBuffer as u32 && i[3] == uint);
+    module.instructions[pointer][3] = uchar;
+    let pointer = module.instructions[pointer][1];
+
+    let elements: Vec<u32> = module.instructions.iter()
+        .filter(|i| opcode(i) == spirv::Op::AccessChain as u32 && i[1] == pointer)
+        .map(|i| i[2])
+        .collect();
+    assert!(!module.instructions.iter().any(|i| opcode(i) == spirv::Op::Load as u32 && elements.contains(&i[3])),
+        "Int8 variant only writes the pixels");
+    let mut index = 0;
+    while index < module.instructions.len() {
+        let i = &module.instructions[index];
+        if opcode(i) == spirv::Op::Store as u32 && elements.contains(&i[1]) {
+            let value = i[2];
+            let byte = module.new_id();
+            module.instructions[index][2] = byte;
+            module.instructions.insert(index, instruction(spirv::Op::UConvert, &[uchar, byte, value]));
+            index += 1;
+        }
+        index += 1;
+    }
+
+    let capabilities = module.position("instruction after the capabilities", |i| opcode(i) != spirv::Op::Capability as u32);
+    module.instructions.insert(capabilities, instruction(spirv::Op::Extension, &string_words("SPV_KHR_8bit_storage")));
+    for capability in [spirv::Capability::StorageBuffer8BitAccess, spirv::Capability::Int8] {
+        module.instructions.insert(capabilities, instruction(spirv::Op::Capability, &[capability as u32]));
+    }
+}
+
+
+// module with the entry point of the WGSL shader as the only one, named main like every compute shader of the backends
+fn generate_spirv(source: &str, entry_point: &str, int8: bool) -> Vec<u8> {
+    let mut module = naga::front::wgsl::parse_str(source)
+        .unwrap_or_else(|error| panic!("{}", error.emit_to_string(source)));
+    module.entry_points.retain(|e| e.name == entry_point);
+    module.entry_points[0].name = "main".to_string();
+
+    let info = validation::validate(&module)
+        .unwrap_or_else(|error| panic!("{MANDELBROT_WGSL} is invalid: {error:?}"));
+    // debug names identify the template constants
+    let options = naga::back::spv::Options {
+        flags: naga::back::spv::WriterFlags::DEBUG,
+        ..Default::default()
+    };
+    let words = naga::back::spv::write_vec(&module, &info, &options, None).unwrap();
+
+    let mut module = Module::parse(&words);
+    make_spec_constants(&mut module);
+    if int8 {
+        narrow_pixels_to_u8(&mut module);
+    }
+    module.assemble().iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
 
 fn main() {
     let out_dir = std::env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={MANDELBROT_WGSL}");
+    println!("cargo:rerun-if-changed=src/shaders/spirv.rs");
+    println!("cargo:rerun-if-changed=src/shaders/validation.rs");
 
-    let mut file = std::fs::File::create(&std::path::Path::new(&out_dir).join("bindings.rs")).unwrap();
+    let mut file = std::fs::File::create(std::path::Path::new(&out_dir).join("bindings.rs")).unwrap();
 
     gl_generator::Registry::new(
         gl_generator::Api::Gl,
@@ -15,4 +176,8 @@ fn main() {
     )
     .write_bindings(gl_generator::GlobalGenerator, &mut file)
     .unwrap();
+
+    let source = std::fs::read_to_string(MANDELBROT_WGSL).unwrap();
+    std::fs::write(std::path::Path::new(&out_dir).join("mandelbrot.spv"), generate_spirv(&source, "main", false)).unwrap();
+    std::fs::write(std::path::Path::new(&out_dir).join("mandelbrot-u8.spv"), generate_spirv(&source, "main_u8", true)).unwrap();
 }
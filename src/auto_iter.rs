@@ -0,0 +1,126 @@
+use crate::backends::cpu::escape_iterations;
+use crate::parameters::Parameters;
+
+use rayon::prelude::*;
+
+
+// budget for the whole set in view, grown linearly with every halving of the view size
+const BASE_ITER: f64 = 100.0;
+const ITER_PER_ZOOM_OCTAVE: f64 = 40.0;
+const BASE_VIEW_SIZE: f64 = 3.0;
+
+// size of the low resolution render used to verify the budget
+const PROBE_SIZE_PX: usize = 128;
+// how much larger budget is used to check whether saturated boundary points really are inside
+const CHECK_ITER_FACTOR: u32 = 4;
+// largest accepted fraction of saturated boundary points that escape with the larger budget
+const MAX_ESCAPING_BOUNDARY_FRACTION: f64 = 0.05;
+
+
+pub fn estimate_max_iter(params: &Parameters) -> u32 {
+    let [xmin, xmax, ymin, ymax] = params.limits;
+    let view_size = (xmax - xmin).abs().min((ymax - ymin).abs());
+    let zoom_octaves = (BASE_VIEW_SIZE / view_size).log2().max(0.0);
+    (BASE_ITER + ITER_PER_ZOOM_OCTAVE * zoom_octaves).round() as u32
+}
+
+
+struct Probe {
+    size: usize,
+    points: Vec<(f64, f64)>,
+    iterations: Vec<u32>
+}
+
+
+impl Probe {
+    fn new(params: &Parameters, max_iter: u32) -> Self {
+        let size = PROBE_SIZE_PX.min(params.img_size_px as usize);
+        let [xmin, xmax, ymin, ymax] = params.limits;
+        let points: Vec<(f64, f64)> = (0..size * size).map(|i| {
+            let (x, y) = ((i % size) as f64 / size as f64, (i / size) as f64 / size as f64);
+            (xmin + (xmax - xmin) * x, ymin + (ymax - ymin) * y)
+        }).collect();
+        let iterations = points.par_iter()
+            .map(|(x, y)| escape_iterations(*x, *y, max_iter, params.precision, params.interior_checks))
+            .collect();
+        Probe { size, points, iterations }
+    }
+}
+
+
+pub fn select_max_iter(params: &Parameters, max_iter_limit: u32) -> u32 {
+    let mut max_iter = estimate_max_iter(params).clamp(1, max_iter_limit);
+    tracing::debug!("Estimated max_iter from zoom level: {max_iter}");
+
+    loop {
+        let probe = Probe::new(params, max_iter);
+        let boundary = crate::refine::saturated_boundary(probe.size, probe.size, |i| probe.iterations[i] == 0);
+        if boundary.is_empty() {
+            break;
+        }
+
+        let check_iter = max_iter.saturating_mul(CHECK_ITER_FACTOR);
+        let escaping = boundary.par_iter().filter(|i| {
+            let (x, y) = probe.points[**i];
+            escape_iterations(x, y, check_iter, params.precision, params.interior_checks) != 0
+        }).count();
+        let escaping_fraction = escaping as f64 / boundary.len() as f64;
+        tracing::debug!(
+            "max_iter {max_iter}: {escaping} of {} saturated boundary points escape within {check_iter} iterations",
+            boundary.len()
+        );
+
+        if escaping_fraction <= MAX_ESCAPING_BOUNDARY_FRACTION {
+            break;
+        }
+        if max_iter == max_iter_limit {
+            tracing::warn!("Iteration budget capped at {max_iter_limit}, {:.1}% of boundary points still saturate", escaping_fraction * 100.0);
+            break;
+        }
+        max_iter = max_iter.saturating_mul(2).min(max_iter_limit);
+    }
+
+    tracing::info!("Auto-selected max_iter: {max_iter}");
+    max_iter
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::parameters::parse_params;
+
+    use super::{estimate_max_iter, select_max_iter};
+
+
+    fn params_for_view(view: [&str; 4]) -> crate::parameters::Parameters {
+        parse_params(&[&["cpu-par-iter", "64", "100", "--"][..], &view].concat()).unwrap()
+    }
+
+
+    #[test]
+    fn estimate_grows_with_zoom() {
+        assert_eq!(estimate_max_iter(&params_for_view(["-2.2", "0.8", "-1.5", "1.5"])), 100);
+        // 2^10 times smaller than the whole set
+        assert_eq!(estimate_max_iter(&params_for_view(["-0.75", "-0.7470703125", "0.1", "0.1029296875"])), 500);
+    }
+
+
+    #[test]
+    fn interior_view_keeps_estimate() {
+        let params = params_for_view(["-0.2", "-0.1", "0.0", "0.1"]);
+        assert_eq!(select_max_iter(&params, u32::MAX), estimate_max_iter(&params));
+    }
+
+
+    #[test]
+    fn boundary_view_doubles_estimate_up_to_limit() {
+        // seahorse valley, full of points escaping late
+        let params = params_for_view(["-0.7475", "-0.7425", "0.1", "0.105"]);
+        let estimate = estimate_max_iter(&params);
+        let max_iter = select_max_iter(&params, u32::MAX);
+        assert!(max_iter > estimate);
+        assert!((max_iter / estimate).is_power_of_two() && max_iter.is_multiple_of(estimate));
+
+        assert_eq!(select_max_iter(&params, estimate + 1), estimate + 1);
+    }
+}
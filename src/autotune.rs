@@ -0,0 +1,223 @@
+use crate::backends::session::GpuSession;
+use crate::parameters::{BackendType, Parameters};
+
+use clap::ValueEnum;
+
+use std::path::{Path, PathBuf};
+
+
+// workgroup shapes tried, all within the 256 invocations every adapter supports
+const WORKGROUP_SIZES: [[u16; 2]; 10] = [
+    [8, 8], [16, 4], [16, 8], [16, 16], [32, 2], [32, 4], [32, 8], [64, 1], [64, 4], [256, 1]
+];
+const PIXELS_PER_INVOCATION: [u16; 3] = [4, 8, 16];
+
+// renders of every configuration after the first one, which compiles the pipeline, the fastest one counts
+const RUNS: usize = 3;
+
+
+// workgroup size and pixels per invocation
+type Configuration = ([u16; 2], u16);
+
+
+// cached configuration: key, workgroup size and pixels per invocation
+type Entry = (String, [u16; 2], u16);
+
+
+// nothing is cached when there is no cache directory, relative ones are ignored like XDG specifies
+fn cache_path() -> Option<PathBuf> {
+    let cache_dir = std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from).filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_dir.join("mandelbrot").join("autotune"))
+}
+
+
+// name of the backend on the command line
+fn backend_name(backend_type: BackendType) -> String {
+    backend_type.to_possible_value().unwrap().get_name().to_string()
+}
+
+
+fn graphics_api(backend: wgpu::Backend) -> &'static str {
+    match backend {
+        wgpu::Backend::Empty => "empty",
+        wgpu::Backend::Vulkan => "vulkan",
+        wgpu::Backend::Metal => "metal",
+        wgpu::Backend::Dx12 => "dx12",
+        wgpu::Backend::Dx11 => "dx11",
+        wgpu::Backend::Gl => "gl",
+        wgpu::Backend::BrowserWebGpu => "webgpu"
+    }
+}
+
+
+// configurations are tuned for a backend, as the SPIR-V and WGSL shaders differ, on an adapter, the key is made of
+// the fields identifying them so that it does not depend on how their types are formatted for debugging
+fn cache_key(backend_type: BackendType, adapter_info: &wgpu::AdapterInfo) -> String {
+    format!("{} {} {:04x}:{:04x} {}", backend_name(backend_type), graphics_api(adapter_info.backend),
+        adapter_info.vendor, adapter_info.device, adapter_info.name)
+}
+
+
+// one line per backend and adapter: WIDTHxHEIGHT PIXELS_PER_INVOCATION KEY
+fn parse_line(line: &str) -> Option<Entry> {
+    let (workgroup_size, line) = line.split_once(' ')?;
+    let (pixels_per_invocation, key) = line.split_once(' ')?;
+    let (width, height) = workgroup_size.split_once('x')?;
+    Some((key.to_string(), [width.parse().ok()?, height.parse().ok()?], pixels_per_invocation.parse().ok()?))
+}
+
+
+fn read_cache(path: &Path) -> Vec<Entry> {
+    std::fs::read_to_string(path)
+        .map(|cache| cache.lines().filter_map(parse_line).collect())
+        .unwrap_or_default()
+}
+
+
+// replaces the entry of the key in the cache file
+fn store(path: &Path, key: String, workgroup_size: [u16; 2], pixels_per_invocation: u16) -> std::io::Result<()> {
+    let mut entries = read_cache(path);
+    entries.retain(|(entry_key, _, _)| *entry_key != key);
+    entries.push((key, workgroup_size, pixels_per_invocation));
+
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let cache: String = entries.iter()
+        .map(|(key, [width, height], pixels)| format!("{width}x{height} {pixels} {key}\n"))
+        .collect();
+    std::fs::write(path, cache)
+}
+
+
+// a configuration which cannot be cached is still used for this render
+fn save(key: String, workgroup_size: [u16; 2], pixels_per_invocation: u16) {
+    let Some(path) = cache_path() else {
+        tracing::warn!("No cache directory (XDG_CACHE_HOME or HOME), the tuned configuration is not saved");
+        return;
+    };
+    match store(&path, key, workgroup_size, pixels_per_invocation) {
+        Ok(()) => tracing::debug!("Tuned configuration saved to {}", path.display()),
+        Err(error) => tracing::error!("Cannot save the tuned configuration to {}: {error}", path.display())
+    }
+}
+
+
+// workgroup size and pixels per invocation tuned for the backend on the adapter it would render on
+pub fn lookup(params: &Parameters) -> Result<Option<Configuration>, Box<dyn std::error::Error>> {
+    let backend_type = params.shader_backend();
+    let backend_prefix = format!("{} ", backend_name(backend_type));
+    let entries: Vec<Entry> = cache_path().map(|path| read_cache(&path)).unwrap_or_default().into_iter()
+        .filter(|(key, _, _)| key.starts_with(&backend_prefix))
+        .collect();
+    // no adapter has to be set up when nothing was tuned for the backend
+    if entries.is_empty() {
+        return Ok(None);
+    }
+    let key = cache_key(backend_type, &crate::backends::wgpu::selected_adapter_info(params)?);
+    Ok(entries.into_iter()
+        .find(|(entry_key, _, _)| *entry_key == key)
+        .map(|(_, workgroup_size, pixels_per_invocation)| (workgroup_size, pixels_per_invocation)))
+}
+
+
+// renders the view with every configuration fitting the image size, the fastest one is used and remembered for the
+// adapter
+pub fn tune(params: &mut Parameters) -> Result<(), Box<dyn std::error::Error>> {
+    let backend_type = params.shader_backend();
+    let adapter_info = crate::backends::wgpu::selected_adapter_info(params)?;
+    // SPIR-V shaders given with --shader may compute a fixed number of pixels per invocation
+    let pixel_options = match (backend_type, &params.shader) {
+        (BackendType::WgpuSpirv, Some(_)) => vec![params.pixels_per_invocation],
+        _ => PIXELS_PER_INVOCATION.to_vec()
+    };
+
+    let mut best: Option<(std::time::Duration, [u16; 2], u16)> = None;
+    for workgroup_size in WORKGROUP_SIZES {
+        for &pixels_per_invocation in &pixel_options {
+            if !(params.img_size_px as u32).is_multiple_of(workgroup_size[0] as u32 * pixels_per_invocation as u32)
+            { continue; }
+
+            let candidate = Parameters { backend_type, workgroup_size, pixels_per_invocation, ..params.clone() };
+            let mut session = GpuSession::new(&candidate)?;
+            session.render(&candidate)?;
+            let mut time = std::time::Duration::MAX;
+            for _ in 0..RUNS {
+                time = time.min(session.render(&candidate)?.computation_time);
+            }
+            tracing::debug!("Workgroup {}x{}, {pixels_per_invocation} pixels per invocation: {time:.1?}",
+                workgroup_size[0], workgroup_size[1]);
+
+            if best.is_none_or(|(best_time, _, _)| time < best_time) {
+                best = Some((time, workgroup_size, pixels_per_invocation));
+            }
+        }
+    }
+
+    let (time, workgroup_size, pixels_per_invocation) = best.expect("No workgroup size fits the image size");
+    tracing::info!("Tuned {backend_type:?} on {}: workgroup {}x{}, {pixels_per_invocation} pixels per invocation ({time:.1?})",
+        adapter_info.name, workgroup_size[0], workgroup_size[1]);
+    save(cache_key(backend_type, &adapter_info), workgroup_size, pixels_per_invocation);
+    params.workgroup_size = workgroup_size;
+    params.pixels_per_invocation = pixels_per_invocation;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn temp_cache(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mandelbrot-autotune-{name}-{}", std::process::id())).join("autotune")
+    }
+
+
+    #[test]
+    fn parses_lines() {
+        assert_eq!(parse_line("32x4 8 wgpu-wgsl gl 10005:0000 llvmpipe (LLVM 15.0.7, 256 bits)"),
+            Some(("wgpu-wgsl gl 10005:0000 llvmpipe (LLVM 15.0.7, 256 bits)".to_string(), [32, 4], 8)));
+        for line in ["", "32x4 8", "32x4 WgpuWgsl", "32 8 WgpuWgsl", "32xa 8 WgpuWgsl", "32x4 -8 WgpuWgsl"] {
+            assert_eq!(parse_line(line), None, "{line}");
+        }
+    }
+
+
+    #[test]
+    fn keys_name_backend_and_adapter() {
+        let adapter_info = wgpu::AdapterInfo {
+            name: "llvmpipe (LLVM 15.0.7, 256 bits)".to_string(),
+            vendor: 0x10005,
+            device: 0,
+            device_type: wgpu::DeviceType::Cpu,
+            backend: wgpu::Backend::Vulkan
+        };
+        assert_eq!(cache_key(BackendType::WgpuSpirv, &adapter_info), "wgpu-spirv vulkan 10005:0000 llvmpipe (LLVM 15.0.7, 256 bits)");
+    }
+
+
+    #[test]
+    fn stores_one_entry_per_key() {
+        let path = temp_cache("store");
+        store(&path, "wgpu-wgsl a".to_string(), [16, 16], 4).unwrap();
+        store(&path, "wgpu-spirv a".to_string(), [8, 8], 8).unwrap();
+        store(&path, "wgpu-wgsl a".to_string(), [64, 1], 16).unwrap();
+        let entries = read_cache(&path);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert_eq!(entries, [
+            ("wgpu-spirv a".to_string(), [8, 8], 8),
+            ("wgpu-wgsl a".to_string(), [64, 1], 16)
+        ]);
+    }
+
+
+    #[test]
+    fn reports_unwritable_cache() {
+        let file = temp_cache("unwritable");
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(&file, "").unwrap();
+        let result = store(&file.join("autotune"), "wgpu-wgsl a".to_string(), [16, 16], 4);
+        std::fs::remove_dir_all(file.parent().unwrap()).unwrap();
+        assert!(result.is_err());
+    }
+}
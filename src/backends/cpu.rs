@@ -1,128 +1,286 @@
+use crate::parameters::{InteriorChecks, Precision, WorkUnit};
+
+use num_traits::Float;
 use rayon::prelude::*;
 
 
 #[derive(Clone, Copy)]
-struct Vec2 {
-    pub x: f32,
-    pub y: f32
+struct Vec2<T> {
+    pub x: T,
+    pub y: T
 }
 
 
-impl Vec2 {
-    pub fn new(x: f32, y: f32) -> Self {
+impl<T: Float> Vec2<T> {
+    pub fn new(x: T, y: T) -> Self {
         Vec2 { x, y }
     }
 
-    pub fn length_sq(&self) -> f32 {
+    pub fn length_sq(&self) -> T {
         self.x * self.x + self.y * self.y
     }
 }
 
 
-impl std::ops::Add<Vec2> for Vec2 {
-    type Output = Vec2;
+impl<T: Float> std::ops::Add<Vec2<T>> for Vec2<T> {
+    type Output = Vec2<T>;
 
-    fn add(self, rhs: Vec2) -> Self::Output {
+    fn add(self, rhs: Vec2<T>) -> Self::Output {
         Vec2::new(self.x + rhs.x, self.y + rhs.y)
     }
 }
 
 
-fn mix(a: f32, b: f32, v: f32) -> f32 {
-    let v = v.clamp(0.0, 1.0);
-    a * (1.0 - v) + b * v
+pub fn mix<T: Float>(a: T, b: T, v: T) -> T {
+    let v = v.max(T::zero()).min(T::one());
+    a * (T::one() - v) + b * v
+}
+
+
+// analytic test for the main cardioid and the period-2 bulb
+pub fn inside_main_bulbs<T: Float>(x: T, y: T) -> bool {
+    let quarter = T::from(0.25).unwrap();
+    let sixteenth = T::from(0.0625).unwrap();
+
+    let xq = x - quarter;
+    let q = xq * xq + y * y;
+    let xb = x + T::one();
+    q * (q + xq) <= quarter * y * y || xb * xb + y * y <= sixteenth
 }
 
 
-fn mandelbrot(c: Vec2, max_iter: u8) -> u8 {
+// escape iterations (0 for points inside the set) and number of iterations actually run
+#[allow(clippy::needless_return)]
+fn orbit<T: Float>(c: Vec2<T>, max_iter: u32, checks: InteriorChecks) -> (u32, u32) {
+    if checks.bulbs && inside_main_bulbs(c.x, c.y) {
+        return (0, 0);
+    }
+
     // mandelbrot
     // z_n+1 = z_n * z_n + c
-    let mut z = Vec2::new(0.0, 0.0);
+    let four = T::from(4.0).unwrap();
+    let mut z = Vec2::new(T::zero(), T::zero());
+
+    // Brent's cycle detection: orbit is compared with a point saved at power of two steps
+    let mut saved = z;
+    let mut period = 1u32;
+    let mut steps = 0u32;
+
     for i in 0..max_iter {
         // if we got outside circle of radius 2 we will diverge to infinity
-        if z.length_sq() > 4.0 {
-            return i;
+        if z.length_sq() > four {
+            return (i, i);
         }
         z = Vec2::new(
             z.x*z.x - z.y*z.y + c.x,
             z.x*z.y + z.y*z.x + c.y
         );
+
+        if checks.periodicity {
+            // cycling orbit never escapes
+            if z.x == saved.x && z.y == saved.y {
+                return (0, i + 1);
+            }
+            steps += 1;
+            if steps == period {
+                saved = z;
+                steps = 0;
+                period *= 2;
+            }
+        }
     }
-    return 0;
+    return (0, max_iter);
 }
 
 
-fn mandelmsaax16(c: Vec2, max_iter: u8, img_size: f32) -> u32 {
-    // uniform distribution of 16 points across pixel
-    let dpx = 1.0 / 8.0 / img_size;
-    let dpx2 = 3.0 / 8.0 / img_size;
-
-    let sum =
-        mandelbrot(c + Vec2::new(-dpx2, -dpx2), max_iter) as u32 +
-        mandelbrot(c + Vec2::new(-dpx,  -dpx2), max_iter) as u32 +
-        mandelbrot(c + Vec2::new( dpx,  -dpx2), max_iter) as u32 +
-        mandelbrot(c + Vec2::new( dpx2, -dpx2), max_iter) as u32 +
+fn mandelbrot<T: Float>(c: Vec2<T>, max_iter: u32, checks: InteriorChecks) -> u32 {
+    orbit(c, max_iter, checks).0
+}
 
-        mandelbrot(c + Vec2::new(-dpx2, -dpx), max_iter) as u32 +
-        mandelbrot(c + Vec2::new(-dpx,  -dpx), max_iter) as u32 +
-        mandelbrot(c + Vec2::new( dpx,  -dpx), max_iter) as u32 +
-        mandelbrot(c + Vec2::new( dpx2, -dpx), max_iter) as u32 +
 
-        mandelbrot(c + Vec2::new(-dpx2, dpx), max_iter) as u32 +
-        mandelbrot(c + Vec2::new(-dpx,  dpx), max_iter) as u32 +
-        mandelbrot(c + Vec2::new( dpx,  dpx), max_iter) as u32 +
-        mandelbrot(c + Vec2::new( dpx2, dpx), max_iter) as u32 +
+// escape iterations and iterations run of all the samples of the pixel at `c`
+fn sample_orbits<'a, T: Float + 'a>(
+    c: Vec2<T>,
+    max_iter: u32,
+    input_parameters: &'a crate::parameters::Parameters,
+    img_size: T
+) -> impl Iterator<Item = (u32, u32)> + 'a {
+    input_parameters.sampling.offsets.iter().map(move |[x, y]| {
+        let offset = Vec2::new(T::from(*x).unwrap() / img_size, T::from(*y).unwrap() / img_size);
+        orbit(c + offset, max_iter, input_parameters.interior_checks)
+    })
+}
 
-        mandelbrot(c + Vec2::new(-dpx2, dpx2), max_iter) as u32 +
-        mandelbrot(c + Vec2::new(-dpx,  dpx2), max_iter) as u32 +
-        mandelbrot(c + Vec2::new( dpx,  dpx2), max_iter) as u32 +
-        mandelbrot(c + Vec2::new( dpx2, dpx2), max_iter) as u32;
 
-    return sum / 16;
+// iterations of all the samples of the pixel at `c`
+fn sample_iterations<'a, T: Float + 'a>(
+    c: Vec2<T>,
+    max_iter: u32,
+    input_parameters: &'a crate::parameters::Parameters,
+    img_size: T
+) -> impl Iterator<Item = u32> + 'a {
+    sample_orbits(c, max_iter, input_parameters, img_size).map(|(iterations, _)| iterations)
 }
 
 
-fn mandelproc(c: Vec2, max_iter: u8, img_size: f32) -> u8 {
-    (mandelmsaax16(c, max_iter, img_size) as f32 / max_iter as f32 * 1.5 * 255.0).clamp(
-        0.0,
+// with refinement 0 is left for pixels which did not escape, the ones which escaped early are at least 1
+pub fn iterations_to_value(iterations: u32, input_parameters: &crate::parameters::Parameters) -> u8 {
+    if iterations == 0 {
+        return 0;
+    }
+    let lowest = if input_parameters.marks_saturated() { 1.0 } else { 0.0 };
+    (iterations as f32 / input_parameters.max_iter as f32 * 1.5 * 255.0).clamp(
+        lowest,
         255.0
     ) as u8
 }
 
 
-fn mandelbrot_for_xy(x: u16, y: u16, input_parameters: &crate::parameters::Parameters) -> u8 {
-    let img_size_f = input_parameters.img_size_px as f32;
+// with refinement rounded up, so that the average is 0 only when none of the samples escaped
+pub fn average_samples(sum: u32, input_parameters: &crate::parameters::Parameters) -> u32 {
+    let samples = input_parameters.sampling.offsets.len() as u32;
+    if input_parameters.marks_saturated() { sum.div_ceil(samples) } else { sum / samples }
+}
+
+
+// iterations of the samples of the pixel averaged, budget - number of iterations to run
+fn average_iterations<T: Float>(c: Vec2<T>, budget: u32, input_parameters: &crate::parameters::Parameters, img_size: T) -> u32 {
+    average_samples(sample_iterations(c, budget, input_parameters, img_size).sum(), input_parameters)
+}
+
+
+// budget - number of iterations to run, max_iter - number of iterations mapped to the brightest value
+fn mandelproc<T: Float>(c: Vec2<T>, budget: u32, input_parameters: &crate::parameters::Parameters, img_size: T) -> u8 {
+    iterations_to_value(average_iterations(c, budget, input_parameters, img_size), input_parameters)
+}
+
+
+fn row_coordinate_with<T: Float>(y: u16, input_parameters: &crate::parameters::Parameters) -> T {
+    mix(
+        T::from(input_parameters.limits[2]).unwrap(),
+        T::from(input_parameters.limits[3]).unwrap(),
+        T::from(y).unwrap() / T::from(input_parameters.img_size_px).unwrap()
+    )
+}
+
 
+// imaginary part of the coordinates of the row, exactly as the kernels compute it
+pub fn row_coordinate(y: u16, input_parameters: &crate::parameters::Parameters) -> f64 {
+    match input_parameters.precision {
+        Precision::Single => row_coordinate_with::<f32>(y, input_parameters) as f64,
+        Precision::Double => row_coordinate_with::<f64>(y, input_parameters)
+    }
+}
+
+
+fn pixel_coordinates<T: Float>(x: u16, y: u16, input_parameters: &crate::parameters::Parameters) -> Vec2<T> {
     let img_x = mix(
-        input_parameters.limits[0],
-        input_parameters.limits[1],
-        x as f32 / img_size_f
-    );
-    let img_y = mix(
-        input_parameters.limits[2],
-        input_parameters.limits[3],
-        y as f32 / img_size_f
+        T::from(input_parameters.limits[0]).unwrap(),
+        T::from(input_parameters.limits[1]).unwrap(),
+        T::from(x).unwrap() / T::from(input_parameters.img_size_px).unwrap()
     );
+    Vec2::new(img_x, row_coordinate_with::<T>(y, input_parameters))
+}
+
+
+fn mandelbrot_for_xy_with<T: Float>(x: u16, y: u16, budget: u32, input_parameters: &crate::parameters::Parameters) -> u8 {
+    let img_size_f = T::from(input_parameters.img_size_px).unwrap();
+    mandelproc(pixel_coordinates::<T>(x, y, input_parameters), budget, input_parameters, img_size_f)
+}
+
+
+// value of the averaged iterations together with the colors of the samples averaged in linear RGB, and the iterations
+// run for them
+fn shaded_pixel_for_xy_with<T: Float>(x: u16, y: u16, input_parameters: &crate::parameters::Parameters) -> (u8, [u8; 4], u32) {
+    let img_size_f = T::from(input_parameters.img_size_px).unwrap();
+    let (iterations, run): (Vec<u32>, Vec<u32>) =
+        sample_orbits(pixel_coordinates::<T>(x, y, input_parameters), input_parameters.budget(), input_parameters, img_size_f).unzip();
+    let value = iterations_to_value(average_samples(iterations.iter().sum(), input_parameters), input_parameters);
+    let color = crate::colormap::average_colors(iterations.iter().map(|i| iterations_to_value(*i, input_parameters)));
+    (value, color, run.iter().sum())
+}
+
+
+// returns 0 if point did not escape within max_iter, otherwise number of iterations it took
+pub fn escape_iterations(x: f64, y: f64, max_iter: u32, precision: Precision, checks: InteriorChecks) -> u32 {
+    match precision {
+        Precision::Single => mandelbrot(Vec2::new(x as f32, y as f32), max_iter, checks),
+        Precision::Double => mandelbrot(Vec2::new(x, y), max_iter, checks)
+    }
+}
+
+
+// averaged iterations the value of the pixel is quantized from
+pub fn average_iterations_for_xy(x: u16, y: u16, input_parameters: &crate::parameters::Parameters) -> u32 {
+    fn average_with<T: Float>(x: u16, y: u16, input_parameters: &crate::parameters::Parameters) -> u32 {
+        let img_size_f = T::from(input_parameters.img_size_px).unwrap();
+        average_iterations(pixel_coordinates::<T>(x, y, input_parameters), input_parameters.budget(), input_parameters, img_size_f)
+    }
+    match input_parameters.precision {
+        Precision::Single => average_with::<f32>(x, y, input_parameters),
+        Precision::Double => average_with::<f64>(x, y, input_parameters)
+    }
+}
+
+
+pub fn mandelbrot_for_xy(x: u16, y: u16, input_parameters: &crate::parameters::Parameters) -> u8 {
+    let budget = input_parameters.budget();
+    match input_parameters.precision {
+        Precision::Single => mandelbrot_for_xy_with::<f32>(x, y, budget, input_parameters),
+        Precision::Double => mandelbrot_for_xy_with::<f64>(x, y, budget, input_parameters)
+    }
+}
+
+
+// value of the pixel together with the iterations run for all its samples
+pub fn pixel_with_cost_for_xy(x: u16, y: u16, input_parameters: &crate::parameters::Parameters) -> (u8, u32) {
+    fn pixel_with<T: Float>(x: u16, y: u16, input_parameters: &crate::parameters::Parameters) -> (u8, u32) {
+        let img_size_f = T::from(input_parameters.img_size_px).unwrap();
+        let c = pixel_coordinates::<T>(x, y, input_parameters);
+        let (iterations, run) = sample_orbits(c, input_parameters.budget(), input_parameters, img_size_f)
+            .fold((0, 0), |(iterations, run), (i, r)| (iterations + i, run + r));
+        (iterations_to_value(average_samples(iterations, input_parameters), input_parameters), run)
+    }
+
+    match input_parameters.precision {
+        Precision::Single => pixel_with::<f32>(x, y, input_parameters),
+        Precision::Double => pixel_with::<f64>(x, y, input_parameters)
+    }
+}
+
 
-    mandelproc(Vec2::new(img_x, img_y), input_parameters.max_iter, img_size_f)
+pub fn shaded_pixel_with_cost_for_xy(x: u16, y: u16, input_parameters: &crate::parameters::Parameters) -> (u8, [u8; 4], u32) {
+    match input_parameters.precision {
+        Precision::Single => shaded_pixel_for_xy_with::<f32>(x, y, input_parameters),
+        Precision::Double => shaded_pixel_for_xy_with::<f64>(x, y, input_parameters)
+    }
+}
+
+
+pub fn shaded_pixel_for_xy(x: u16, y: u16, input_parameters: &crate::parameters::Parameters) -> (u8, [u8; 4]) {
+    let (value, color, _) = shaded_pixel_with_cost_for_xy(x, y, input_parameters);
+    (value, color)
 }
 
 
 pub fn run_cpu_loops(params: &crate::parameters::Parameters) -> crate::result::ComputeResult {
     let start_time = std::time::Instant::now();
-    let mut data = vec![0u8; params.img_size_px as usize * params.img_size_px as usize];
+    let mut data = vec![0u8; params.rows.len() * params.img_size_px as usize];
     let init_time = start_time.elapsed();
-    for y in 0..params.img_size_px {
-        let row_idx = y as usize * params.img_size_px as usize;
+    for y in params.rows.clone() {
+        let row_idx = (y - params.rows.start) as usize * params.img_size_px as usize;
         for x in 0..params.img_size_px {
-            data[row_idx + x as usize] = mandelbrot_for_xy(x, y, &params);
+            data[row_idx + x as usize] = mandelbrot_for_xy(x, y, params);
         }
     }
 
     crate::result::ComputeResult {
         data,
+        colors: None,
+        costs: None,
         initialization_time: init_time,
         computation_time: start_time.elapsed() - init_time,
+        computation_time_estimated: false,
         data_fetch_time: std::time::Duration::ZERO
     }
 }
@@ -131,32 +289,137 @@ pub fn run_cpu_loops(params: &crate::parameters::Parameters) -> crate::result::C
 pub fn run_cpu_iter(params: &crate::parameters::Parameters) -> crate::result::ComputeResult {
     let start_time = std::time::Instant::now();
     let img_size_px = params.img_size_px as usize;
-    let data = (0..(img_size_px * img_size_px)).map(|i|{
-        let (x, y) = (i % img_size_px, i / img_size_px);
-        mandelbrot_for_xy(x as u16, y as u16, &params)
+    let data = (0..(img_size_px * params.rows.len())).map(|i|{
+        let (x, y) = (i % img_size_px, params.rows.start as usize + i / img_size_px);
+        mandelbrot_for_xy(x as u16, y as u16, params)
     }).collect();
 
     crate::result::ComputeResult {
         data,
+        colors: None,
+        costs: None,
         initialization_time: std::time::Duration::ZERO,
         computation_time: start_time.elapsed(),
+        computation_time_estimated: false,
         data_fetch_time: std::time::Duration::ZERO
     }
 }
 
 
+// interleaves bits of tile coordinates, so that consecutive tiles stay close to each other
+fn morton_code(x: u32, y: u32) -> u64 {
+    fn spread(v: u32) -> u64 {
+        (0..32).fold(0u64, |acc, bit| acc | ((v as u64 >> bit) & 1) << (2 * bit))
+    }
+    spread(x) | spread(y) << 1
+}
+
+
+pub fn with_thread_pool<R: Send>(params: &crate::parameters::Parameters, f: impl FnOnce() -> R + Send) -> R {
+    match &params.thread_pool {
+        Some(pool) => pool.install(f),
+        None => f()
+    }
+}
+
+
+fn render_tiles<P: Copy + Default + Send>(
+    params: &crate::parameters::Parameters,
+    morton_order: bool,
+    pixel: &(impl Fn(u16, u16) -> P + Sync)
+) -> Vec<P> {
+    let img_size_px = params.img_size_px as usize;
+    let tile_size = params.tile_size_px as usize;
+    let rows = params.rows.start as usize..params.rows.end as usize;
+
+    let mut tiles: Vec<(usize, usize)> = (rows.start..rows.end).step_by(tile_size)
+        .flat_map(|y| (0..img_size_px).step_by(tile_size).map(move |x| (x, y)))
+        .collect();
+    if morton_order {
+        tiles.sort_by_key(|(x, y)| morton_code((x / tile_size) as u32, ((y - rows.start) / tile_size) as u32));
+    }
+
+    let rendered: Vec<Vec<P>> = tiles.par_iter().map(|(x0, y0)| {
+        let (x1, y1) = ((x0 + tile_size).min(img_size_px), (y0 + tile_size).min(rows.end));
+        (*y0..y1).flat_map(|y| (*x0..x1).map(move |x| (x, y)))
+            .map(|(x, y)| pixel(x as u16, y as u16))
+            .collect()
+    }).collect();
+
+    let mut data = vec![P::default(); img_size_px * rows.len()];
+    for ((x0, y0), tile) in tiles.iter().zip(rendered) {
+        let tile_width = (x0 + tile_size).min(img_size_px) - x0;
+        for (tile_row, y) in tile.chunks(tile_width).zip(*y0..) {
+            let offset = (y - rows.start) * img_size_px + x0;
+            data[offset..offset + tile_width].copy_from_slice(tile_row);
+        }
+    }
+    data
+}
+
+
+// pixels of the requested rows scheduled in the work units of the params
+fn render_pixels<P: Copy + Default + Send>(
+    params: &crate::parameters::Parameters,
+    pixel: impl Fn(u16, u16) -> P + Sync
+) -> Vec<P> {
+    let img_size_px = params.img_size_px as usize;
+    with_thread_pool(params, || match params.work_unit {
+        WorkUnit::Pixels => (0..(img_size_px * params.rows.len())).into_par_iter().map(|i|{
+            let (x, y) = (i % img_size_px, params.rows.start as usize + i / img_size_px);
+            pixel(x as u16, y as u16)
+        }).collect(),
+        WorkUnit::Rows => {
+            let mut data = vec![P::default(); img_size_px * params.rows.len()];
+            data.par_chunks_mut(img_size_px).zip(params.rows.clone()).for_each(|(row, y)| {
+                for (x, value) in row.iter_mut().enumerate() {
+                    *value = pixel(x as u16, y);
+                }
+            });
+            data
+        },
+        WorkUnit::Tiles => render_tiles(params, false, &pixel),
+        WorkUnit::MortonTiles => render_tiles(params, true, &pixel)
+    })
+}
+
+
 pub fn run_cpu_par_iter(params: &crate::parameters::Parameters) -> crate::result::ComputeResult {
     let start_time = std::time::Instant::now();
     let img_size_px = params.img_size_px as usize;
-    let data = (0..(img_size_px * img_size_px)).into_par_iter().map(|i|{
-        let (x, y) = (i % img_size_px, i / img_size_px);
-        mandelbrot_for_xy(x as u16, y as u16, &params)
-    }).collect();
+
+    if params.sampling.color_average {
+        // colors need every sample, so pixels are shaded one by one whatever the work unit
+        let shaded: Vec<(u8, [u8; 4], u32)> = with_thread_pool(params, || (0..(img_size_px * params.rows.len())).into_par_iter().map(|i| {
+            let (x, y) = (i % img_size_px, params.rows.start as usize + i / img_size_px);
+            shaded_pixel_with_cost_for_xy(x as u16, y as u16, params)
+        }).collect());
+        return crate::result::ComputeResult {
+            data: shaded.iter().map(|(value, _, _)| *value).collect(),
+            colors: Some(shaded.iter().map(|(_, color, _)| *color).collect()),
+            costs: params.cost_map.is_some().then(|| shaded.iter().map(|(_, _, cost)| *cost).collect()),
+            initialization_time: std::time::Duration::ZERO,
+            computation_time: start_time.elapsed(),
+            computation_time_estimated: false,
+            data_fetch_time: std::time::Duration::ZERO
+        };
+    }
+
+    // iterations run are counted only for the cost map
+    let (data, costs) = if params.cost_map.is_some() {
+        let (data, costs) = render_pixels(params, |x, y| pixel_with_cost_for_xy(x, y, params)).into_iter().unzip();
+        (data, Some(costs))
+    } else {
+        (render_pixels(params, |x, y| mandelbrot_for_xy(x, y, params)), None)
+    };
 
     crate::result::ComputeResult {
         data,
+        colors: None,
+        costs,
         initialization_time: std::time::Duration::ZERO,
         computation_time: start_time.elapsed(),
+        computation_time_estimated: false,
         data_fetch_time: std::time::Duration::ZERO
     }
 }
@@ -0,0 +1,121 @@
+use crate::gl;
+use crate::parameters::GlPlatform;
+
+use khronos_egl as egl;
+
+
+type Egl = egl::DynamicInstance<egl::EGL1_5>;
+
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+const PLATFORM_DEVICE_EXT: egl::Enum = 0x313F;
+
+// devices queried at most from EGL_EXT_device_enumeration
+const MAX_DEVICES: usize = 16;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Api {
+    OpenGl,
+    OpenGlEs
+}
+
+
+// context current on the thread which created it, without any surface, window or event loop
+pub struct Context {
+    egl: Egl,
+    display: egl::Display,
+    context: egl::Context,
+    pub api: Api
+}
+
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        self.egl.make_current(self.display, None, None, None).unwrap();
+        self.egl.destroy_context(self.display, self.context).unwrap();
+        self.egl.terminate(self.display).unwrap();
+    }
+}
+
+
+// first of the devices from EGL_EXT_device_enumeration, rendering devices and software ones alike
+unsafe fn first_device(egl: &Egl) -> Result<*mut std::ffi::c_void, Box<dyn std::error::Error>> {
+    type QueryDevices = unsafe extern "system" fn(egl::Int, *mut *mut std::ffi::c_void, *mut egl::Int) -> egl::Boolean;
+    let query_devices: QueryDevices = std::mem::transmute(
+        egl.get_proc_address("eglQueryDevicesEXT").ok_or("EGL device enumeration is not supported")?
+    );
+
+    let mut devices = [std::ptr::null_mut(); MAX_DEVICES];
+    let mut count: egl::Int = 0;
+    if query_devices(devices.len() as egl::Int, devices.as_mut_ptr(), &mut count as *mut egl::Int) != egl::TRUE || count == 0 {
+        return Err("No EGL device found".into());
+    }
+    tracing::debug!("EGL devices found: {count}");
+    Ok(devices[0])
+}
+
+
+unsafe fn display(egl: &Egl, platform: GlPlatform) -> Result<egl::Display, Box<dyn std::error::Error>> {
+    let native_display = match platform {
+        GlPlatform::Surfaceless => std::ptr::null_mut(),
+        GlPlatform::Device => first_device(egl)?
+    };
+    let platform_enum = match platform {
+        GlPlatform::Surfaceless => PLATFORM_SURFACELESS_MESA,
+        GlPlatform::Device => PLATFORM_DEVICE_EXT
+    };
+    let display = egl.get_platform_display(platform_enum, native_display, &[egl::ATTRIB_NONE])
+        .map_err(|e| format!("EGL platform {platform:?} is not available: {e}"))?;
+
+    let (major, minor) = egl.initialize(display).map_err(|e| format!("Cannot initialize EGL on {platform:?} platform: {e}"))?;
+    tracing::debug!("EGL {major}.{minor} on {platform:?} platform");
+    Ok(display)
+}
+
+
+unsafe fn try_create_context(egl: &Egl, display: egl::Display, api: Api, (major, minor): (egl::Int, egl::Int), debug: bool)
+-> Option<egl::Context> {
+    let (egl_api, renderable_type) = match api {
+        Api::OpenGl => (egl::OPENGL_API, egl::OPENGL_BIT),
+        Api::OpenGlEs => (egl::OPENGL_ES_API, egl::OPENGL_ES3_BIT)
+    };
+    egl.bind_api(egl_api).ok()?;
+    // no surface is ever created, the context is made current without one
+    let config = egl.choose_first_config(display, &[
+        egl::RENDERABLE_TYPE, renderable_type,
+        egl::SURFACE_TYPE, 0,
+        egl::NONE
+    ]).ok()??;
+
+    let mut attributes = vec![egl::CONTEXT_MAJOR_VERSION, major, egl::CONTEXT_MINOR_VERSION, minor];
+    if api == Api::OpenGl {
+        attributes.extend([egl::CONTEXT_OPENGL_PROFILE_MASK, egl::CONTEXT_OPENGL_CORE_PROFILE_BIT]);
+    }
+    if debug {
+        attributes.extend([egl::CONTEXT_OPENGL_DEBUG, egl::TRUE as egl::Int]);
+    }
+    attributes.push(egl::NONE);
+    egl.create_context(display, config, None, &attributes).ok()
+}
+
+
+// context of the first of the APIs and versions the platform can create, made current with the GL functions loaded
+pub unsafe fn create_context(platform: GlPlatform, requests: &[(Api, (egl::Int, egl::Int))], debug: bool)
+-> Result<Context, Box<dyn std::error::Error>> {
+    let egl = Egl::load_required().map_err(|e| format!("EGL library not found: {e}"))?;
+    let display = display(&egl, platform)?;
+
+    let created = requests.iter()
+        .find_map(|(api, version)| try_create_context(&egl, display, *api, *version, debug).map(|context| (*api, context)));
+    let Some((api, context)) = created else {
+        egl.terminate(display)?;
+        return Err(format!("None of the contexts {requests:?} can be created on {platform:?} platform").into());
+    };
+    // dropped on error, which releases the display as well
+    let context = Context { egl, display, context, api };
+    context.egl.make_current(display, None, None, Some(context.context))?;
+
+    gl::load_with(|s| context.egl.get_proc_address(s).map_or(std::ptr::null(), |f| f as *const std::ffi::c_void));
+
+    Ok(context)
+}
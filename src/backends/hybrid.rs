@@ -0,0 +1,165 @@
+use crate::backends::session::GpuSession;
+use crate::parameters::{BackendType, Parameters, WorkUnit};
+use crate::result::ComputeResult;
+
+use std::ops::Range;
+use std::sync::Mutex;
+
+
+// how long a band should take, long enough to amortize per band overhead, short enough to rebalance in time
+const TARGET_BAND_TIME: std::time::Duration = std::time::Duration::from_millis(100);
+
+
+struct Bands {
+    rendered: Vec<(Range<u16>, ComputeResult)>,
+    busy_time: std::time::Duration
+}
+
+
+impl Bands {
+    fn rows(&self) -> usize {
+        self.rendered.iter().map(|(rows, _)| rows.len()).sum()
+    }
+
+    fn rows_per_second(&self) -> Option<f64> {
+        (!self.rendered.is_empty()).then(|| self.rows() as f64 / self.busy_time.as_secs_f64())
+    }
+}
+
+
+fn gcd(a: u16, b: u16) -> u16 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+
+// bands are multiples of the GPU workgroup height, so no dispatched row is wasted,
+// and of the CPU tile size when tiles are scheduled, so no tile is cut by a band edge
+fn band_alignment(params: &Parameters) -> u16 {
+    let workgroup_rows = params.workgroup_size[1].max(1);
+    match params.work_unit {
+        WorkUnit::Tiles | WorkUnit::MortonTiles => {
+            let tile_rows = params.tile_size_px.max(1);
+            (workgroup_rows / gcd(workgroup_rows, tile_rows)).saturating_mul(tile_rows)
+        }
+        _ => workgroup_rows
+    }
+}
+
+
+fn take_band(remaining: &Mutex<Range<u16>>, rows: u16, alignment: u16) -> Option<Range<u16>> {
+    let mut remaining = remaining.lock().unwrap();
+    if remaining.is_empty() {
+        return None;
+    }
+    // leaves at least half of the rows to the other device, the throughput measured on a few rows can be far off
+    let rows = rows.min((remaining.len() as u16 / 2).next_multiple_of(alignment).max(alignment));
+    let band = remaining.start..remaining.end.min(remaining.start.saturating_add(rows));
+    remaining.start = band.end;
+    Some(band)
+}
+
+
+// renders bands with the backend until no rows remain, every next band is sized after the throughput measured so far
+fn render_bands<E>(
+    params: &Parameters,
+    remaining: &Mutex<Range<u16>>,
+    alignment: u16,
+    mut render: impl FnMut(&Parameters) -> Result<ComputeResult, E>
+) -> Result<Bands, E> {
+    let mut bands = Bands { rendered: Vec::new(), busy_time: std::time::Duration::ZERO };
+    loop {
+        let max_rows = (u16::MAX - alignment) as f64;
+        let rows = bands.rows_per_second()
+            .map_or(alignment, |speed| (speed * TARGET_BAND_TIME.as_secs_f64()).min(max_rows) as u16)
+            // bands grow gradually, cost of rows varies a lot across the image
+            .min(bands.rendered.last().map_or(u16::MAX / 2, |(rows, _)| (rows.len() as u16).saturating_mul(2)))
+            .max(1)
+            .next_multiple_of(alignment);
+        let band = match take_band(remaining, rows, alignment) {
+            Some(band) => band,
+            None => return Ok(bands)
+        };
+
+        let start_time = std::time::Instant::now();
+        let result = render(&Parameters { rows: band.clone(), ..params.clone() })?;
+        bands.busy_time += start_time.elapsed();
+        bands.rendered.push((band, result));
+    }
+}
+
+
+pub fn run_hybrid(params: &Parameters, session: &mut Option<GpuSession>) -> Result<ComputeResult, Box<dyn std::error::Error>> {
+    let start_time = std::time::Instant::now();
+    let remaining = Mutex::new(params.rows.clone());
+    let alignment = band_alignment(params);
+
+    // GPU contexts are driven from the calling thread, the CPU renders its bands on a spawned one
+    let (gpu_bands, cpu_bands) = std::thread::scope(|scope| {
+        let cpu = scope.spawn(|| {
+            let cpu_params = Parameters { backend_type: BackendType::CpuParIter, ..params.clone() };
+            render_bands(&cpu_params, &remaining, alignment, |band_params| {
+                Ok::<_, std::convert::Infallible>(crate::backends::cpu::run_cpu_par_iter(band_params))
+            })
+        });
+
+        let gpu_params = Parameters { backend_type: params.hybrid_gpu_backend, ..params.clone() };
+        // single adapter session is set up before the first band is timed, and kept for the next render
+        let prepared = if gpu_params.all_adapters { Ok(()) } else { crate::backends::session::prepare(session, &gpu_params) };
+        let gpu_bands = prepared.and_then(|()| render_bands(&gpu_params, &remaining, alignment, |band_params| {
+            crate::get_result_from_backend(band_params, session)
+        }));
+        if gpu_bands.is_err() {
+            // the image is not finished without the GPU bands, no point in rendering the rest on the CPU
+            let mut remaining = remaining.lock().unwrap();
+            remaining.start = remaining.end;
+        }
+        (gpu_bands, cpu.join().unwrap())
+    });
+    let gpu_bands = gpu_bands?;
+    let Ok(cpu_bands) = cpu_bands;
+
+    for (backend_type, bands) in [(params.hybrid_gpu_backend, &gpu_bands), (BackendType::CpuParIter, &cpu_bands)] {
+        tracing::debug!("{backend_type:?} rendered {} rows in {} bands, {:.0} rows/s",
+            bands.rows(), bands.rendered.len(), bands.rows_per_second().unwrap_or(0.0));
+    }
+
+    let bands: Vec<_> = gpu_bands.rendered.iter().chain(cpu_bands.rendered.iter()).collect();
+    let (data, colors) = crate::result::stitch_bands(params, &bands);
+
+    Ok(ComputeResult {
+        data,
+        colors,
+        costs: None,
+        initialization_time: std::time::Duration::ZERO,
+        // wall time of both devices working in parallel, measured on the CPU
+        computation_time: start_time.elapsed(),
+        computation_time_estimated: true,
+        data_fetch_time: std::time::Duration::ZERO
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn aligns_bands_to_workgroups_and_tiles() {
+        let params = crate::parameters::parse_params(&["hybrid", "--workgroup-size", "8x24", "--", "96", "255", "-2", "1", "-1.5", "1.5"]).unwrap();
+        assert_eq!(band_alignment(&params), 24);
+        let params = Parameters { work_unit: WorkUnit::Tiles, tile_size_px: 32, ..params };
+        assert_eq!(band_alignment(&params), 96);
+    }
+
+
+    #[test]
+    fn takes_aligned_bands() {
+        let remaining = Mutex::new(0..100);
+        assert_eq!(take_band(&remaining, 48, 24), Some(0..48));
+        // at most half of the rest, rounded up to the alignment
+        assert_eq!(take_band(&remaining, 48, 24), Some(48..96));
+        assert_eq!(take_band(&remaining, 48, 24), Some(96..100));
+        assert_eq!(take_band(&remaining, 48, 24), None);
+    }
+}
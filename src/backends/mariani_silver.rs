@@ -0,0 +1,158 @@
+use crate::backends::cpu::{average_iterations_for_xy, iterations_to_value, with_thread_pool};
+use crate::parameters::Parameters;
+
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+
+// rectangles with any side shorter than that are not worth subdividing
+const MIN_RECT_SIZE_PX: usize = 8;
+
+const NOT_COMPUTED: u32 = u32::MAX;
+
+
+#[derive(Copy, Clone)]
+struct Rect {
+    // inclusive bounds, neighbouring rectangles share their borders
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize
+}
+
+
+struct Canvas<'a> {
+    params: &'a Parameters,
+    size: usize,
+    first_row: usize,
+    // averaged iterations, borders are compared before they are quantized to values, which would merge neighbouring
+    // iteration counts
+    pixels: Vec<AtomicU32>,
+    iterated: AtomicUsize
+}
+
+
+impl<'a> Canvas<'a> {
+    fn new(params: &'a Parameters) -> Self {
+        let size = params.img_size_px as usize;
+        Canvas {
+            params,
+            size,
+            first_row: params.rows.start as usize,
+            pixels: (0..size * params.rows.len()).map(|_| AtomicU32::new(NOT_COMPUTED)).collect(),
+            iterated: AtomicUsize::new(0)
+        }
+    }
+
+    fn compute(&self, x: usize, y: usize) -> u32 {
+        let pixel = &self.pixels[(y - self.first_row) * self.size + x];
+        // borders are shared, so pixel could have been computed already by the neighbour
+        let value = pixel.load(Ordering::Relaxed);
+        if value != NOT_COMPUTED {
+            return value;
+        }
+        // neighbours computing the pixel at the same time get the same value, it is counted once
+        let value = average_iterations_for_xy(x as u16, y as u16, self.params);
+        if pixel.compare_exchange(NOT_COMPUTED, value, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            self.iterated.fetch_add(1, Ordering::Relaxed);
+        }
+        value
+    }
+
+    fn fill(&self, x: usize, y: usize, value: u32) {
+        self.pixels[(y - self.first_row) * self.size + x].store(value, Ordering::Relaxed);
+    }
+
+    fn process(&self, rect: Rect) {
+        if rect.x1 - rect.x0 < MIN_RECT_SIZE_PX || rect.y1 - rect.y0 < MIN_RECT_SIZE_PX {
+            for y in rect.y0..=rect.y1 {
+                for x in rect.x0..=rect.x1 {
+                    self.compute(x, y);
+                }
+            }
+            return;
+        }
+
+        let first = self.compute(rect.x0, rect.y0);
+        let mut uniform = true;
+        for x in rect.x0..=rect.x1 {
+            uniform &= self.compute(x, rect.y0) == first;
+            uniform &= self.compute(x, rect.y1) == first;
+        }
+        for y in rect.y0..=rect.y1 {
+            uniform &= self.compute(rect.x0, y) == first;
+            uniform &= self.compute(rect.x1, y) == first;
+        }
+
+        if uniform {
+            for y in rect.y0 + 1..rect.y1 {
+                for x in rect.x0 + 1..rect.x1 {
+                    self.fill(x, y, first);
+                }
+            }
+            return;
+        }
+
+        let (xm, ym) = ((rect.x0 + rect.x1) / 2, (rect.y0 + rect.y1) / 2);
+        rayon::join(
+            || rayon::join(
+                || self.process(Rect { x0: rect.x0, y0: rect.y0, x1: xm, y1: ym }),
+                || self.process(Rect { x0: xm, y0: rect.y0, x1: rect.x1, y1: ym })
+            ),
+            || rayon::join(
+                || self.process(Rect { x0: rect.x0, y0: ym, x1: xm, y1: rect.y1 }),
+                || self.process(Rect { x0: xm, y0: ym, x1: rect.x1, y1: rect.y1 })
+            )
+        );
+    }
+}
+
+
+pub fn run_cpu_mariani_silver(params: &Parameters) -> crate::result::ComputeResult {
+    let start_time = std::time::Instant::now();
+    let canvas = Canvas::new(params);
+    let init_time = start_time.elapsed();
+
+    with_thread_pool(params, || canvas.process(Rect {
+        x0: 0,
+        y0: params.rows.start as usize,
+        x1: canvas.size - 1,
+        y1: params.rows.end as usize - 1
+    }));
+
+    let iterated = canvas.iterated.load(Ordering::Relaxed);
+    let total = canvas.pixels.len();
+    tracing::info!("Iterated pixels: {iterated} of {total} ({:.1}%)", iterated as f64 / total as f64 * 100.0);
+
+    let data = canvas.pixels.into_iter().map(|p| iterations_to_value(p.into_inner(), params)).collect();
+
+    crate::result::ComputeResult {
+        data,
+        colors: None,
+        costs: None,
+        initialization_time: init_time,
+        computation_time: start_time.elapsed() - init_time,
+        computation_time_estimated: false,
+        data_fetch_time: std::time::Duration::ZERO
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::parameters::parse_params;
+
+
+    // rectangles are filled only when their borders have exactly the same iterations, so the image is the same
+    #[test]
+    fn matches_cpu_par_iter() {
+        let views = [["-2.2", "0.8", "-1.5", "1.5"], ["-0.7475", "-0.7425", "0.1", "0.105"]];
+        for (view, samples) in views.iter().flat_map(|view| [(view, "1"), (view, "16")]) {
+            let args = [&["cpu-mariani-silver", "128", "500", "--samples", samples, "--"][..], view].concat();
+            let params = parse_params(&args).unwrap();
+            let expected = crate::backends::cpu::run_cpu_par_iter(&params).data;
+            let data = super::run_cpu_mariani_silver(&params).data;
+            let differing = data.iter().zip(&expected).filter(|(a, b)| a != b).count();
+            assert_eq!(differing, 0, "{view:?} with {samples} samples");
+        }
+    }
+}
@@ -1,3 +1,9 @@
 pub mod cpu;
+pub mod egl;
+pub mod hybrid;
+pub mod mariani_silver;
 pub mod opengl;
+pub mod opengl_fragment;
+pub mod session;
+pub mod simd;
 pub mod wgpu;
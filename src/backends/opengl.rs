@@ -1,7 +1,7 @@
+use crate::backends::egl::{Api, Context};
 use crate::gl;
-use crate::parameters::Parameters;
+use crate::parameters::{BackendType, Parameters};
 
-use glutin::platform::unix::HeadlessContextExt;
 use gl::types::*;
 
 
@@ -85,6 +85,20 @@ unsafe fn verify_opengl_version() {
 }
 
 
+// headless context made current on the calling thread, the first of the requested ones the platform can create
+pub unsafe fn create_context(params: &Parameters, requests: &[(Api, (i32, i32))]) -> Result<Context, Box<dyn std::error::Error>> {
+    let context = crate::backends::egl::create_context(params.gl_platform, requests, DEBUG_CTX)?;
+
+    // debug output is core since 4.3, older contexts may lack it
+    if DEBUG_CTX && has_extension("GL_KHR_debug") {
+        setup_debug_callback();
+    }
+
+    verify_opengl_version();
+    Ok(context)
+}
+
+
 #[allow(dead_code)]
 unsafe fn verify_spirv_support() {
     let mut binary_formats_count: GLint = 0;
@@ -99,157 +113,442 @@ unsafe fn verify_spirv_support() {
 }
 
 
-unsafe fn verify_spirv_extension_support() {
+unsafe fn has_extension(name: &str) -> bool {
     let mut extensions_count: GLint = 0;
     gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut extensions_count as *mut GLint);
     verify_error();
 
-    (0..extensions_count).into_iter().any(|i|{
+    (0..extensions_count).any(|i|{
         let e = gl::GetStringi(gl::EXTENSIONS, i as GLuint);
         verify_error();
-        std::ffi::CStr::from_ptr(e as *const i8).to_str().unwrap() == "GL_ARB_gl_spirv"
-    }).then_some(()).expect("SPIR-V extension is not supported");
+        std::ffi::CStr::from_ptr(e as *const i8).to_str().unwrap() == name
+    })
 }
 
 
-unsafe fn compile_program(u8_capability: bool) -> GLuint {
-    let shader = gl::CreateShader(gl::COMPUTE_SHADER);
-    verify_error();
+unsafe fn verify_spirv_extension_support() {
+    has_extension("GL_ARB_gl_spirv").then_some(()).expect("SPIR-V extension is not supported");
+}
 
-    let shader_binary: &[u8] = if u8_capability {
-        include_bytes!("../shaders/mandelbrot.rs.spv")
-    } else {
-        include_bytes!("../shaders/mandelbrot-u32.rs.spv")
-    };
-    gl::ShaderBinary(1, &shader as *const GLuint, gl::SHADER_BINARY_FORMAT_SPIR_V_ARB, shader_binary.as_ptr() as *const GLvoid, shader_binary.len() as GLsizei);
-    verify_error();
 
-    let entry_point_name = std::ffi::CString::new("main").unwrap();
-    gl::SpecializeShader(shader, entry_point_name.as_ptr(), 0, std::ptr::null(), std::ptr::null());
-    verify_error();
+unsafe fn shader_info_log(shader: GLuint) -> String {
+    let mut log_length: GLint = 0;
+    gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut log_length as *mut GLint);
+    let mut log = vec![0u8; log_length.max(1) as usize];
+    gl::GetShaderInfoLog(shader, log.len() as GLsizei, std::ptr::null_mut(), log.as_mut_ptr() as *mut GLchar);
+    std::ffi::CStr::from_bytes_until_nul(&log).unwrap().to_string_lossy().into_owned()
+}
+
+
+unsafe fn program_info_log(program: GLuint) -> String {
+    let mut log_length: GLint = 0;
+    gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut log_length as *mut GLint);
+    let mut log = vec![0u8; log_length.max(1) as usize];
+    gl::GetProgramInfoLog(program, log.len() as GLsizei, std::ptr::null_mut(), log.as_mut_ptr() as *mut GLchar);
+    std::ffi::CStr::from_bytes_until_nul(&log).unwrap().to_string_lossy().into_owned()
+}
+
 
+unsafe fn verify_compiled(shader: GLuint) {
     let mut compiled_status: GLint = 0;
     gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut compiled_status as *mut GLint);
     if compiled_status != gl::TRUE as GLint {
-        panic!("Shader specialization/compilation failed");
+        panic!("Shader specialization/compilation failed:\n{}", shader_info_log(shader));
     }
+}
+
 
+#[allow(clippy::needless_return)]
+pub unsafe fn link_program(shaders: &[GLuint]) -> GLuint {
     let program = gl::CreateProgram();
     verify_error();
 
-    gl::AttachShader(program, shader);
-    verify_error();
+    for shader in shaders {
+        gl::AttachShader(program, *shader);
+        verify_error();
+    }
 
     gl::LinkProgram(program);
     verify_error();
 
+    let mut link_status: GLint = 0;
+    gl::GetProgramiv(program, gl::LINK_STATUS, &mut link_status as *mut GLint);
+    if link_status != gl::TRUE as GLint {
+        panic!("Program linking failed:\n{}", program_info_log(program));
+    }
+
     return program;
 }
 
 
-pub unsafe fn run_opengl(params: &Parameters) -> crate::result::ComputeResult {
-    let start_time = std::time::Instant::now();
+// the driver lays the uniform block of GLSL programs out, the offsets of its members have to be the ones of the uniform
+// buffer, members the shaders do not read may be left out
+pub unsafe fn verify_uniform_layout(program: GLuint) -> Result<(), String> {
+    let mut blocks: GLint = 0;
+    gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_BLOCKS, &mut blocks as *mut GLint);
+    verify_error();
+    if blocks != 1 {
+        return Err(format!("Program has {blocks} uniform blocks, expected one"));
+    }
 
-    let el = glutin::event_loop::EventLoop::new();
-    let ctx = glutin::ContextBuilder::new()
-        .with_gl(glutin::GlRequest::Specific(
-            glutin::Api::OpenGl, (4, 6)
-        ))
-        .with_gl_profile(glutin::GlProfile::Core)
-        .with_hardware_acceleration(Some(true))
-        .with_gl_debug_flag(DEBUG_CTX)
-        .build_surfaceless(&el).unwrap();
-    let ctx = ctx.make_current().unwrap();
+    let mut size: GLint = 0;
+    gl::GetActiveUniformBlockiv(program, 0, gl::UNIFORM_BLOCK_DATA_SIZE, &mut size as *mut GLint);
+    verify_error();
+    if size as usize > crate::shaders::provider::input_parameters_size() {
+        return Err(format!("Uniform block of {size} bytes is larger than the {} bytes of the uniform buffer",
+            crate::shaders::provider::input_parameters_size()));
+    }
 
-    gl::load_with(|s| ctx.get_proc_address(s) as *const _);
+    let mut count: GLint = 0;
+    gl::GetActiveUniformBlockiv(program, 0, gl::UNIFORM_BLOCK_ACTIVE_UNIFORMS, &mut count as *mut GLint);
+    verify_error();
+    let mut indices = vec![0; count as usize];
+    gl::GetActiveUniformBlockiv(program, 0, gl::UNIFORM_BLOCK_ACTIVE_UNIFORM_INDICES, indices.as_mut_ptr());
+    verify_error();
+    let indices: Vec<GLuint> = indices.into_iter().map(|index| index as GLuint).collect();
+    let mut offsets = vec![0; indices.len()];
+    gl::GetActiveUniformsiv(program, count, indices.as_ptr(), gl::UNIFORM_OFFSET, offsets.as_mut_ptr());
+    verify_error();
 
-    if DEBUG_CTX {
-        setup_debug_callback();
+    let layout = crate::shaders::provider::input_parameters_layout();
+    for (index, offset) in indices.into_iter().zip(offsets) {
+        let mut name = vec![0u8; 256];
+        let mut length: GLsizei = 0;
+        let mut array_size: GLint = 0;
+        let mut uniform_type: GLenum = 0;
+        gl::GetActiveUniform(program, index, name.len() as GLsizei, &mut length as *mut GLsizei,
+            &mut array_size as *mut GLint, &mut uniform_type as *mut GLenum, name.as_mut_ptr() as *mut GLchar);
+        verify_error();
+        // members are named after the block or its instance, arrays after their first element
+        let name = String::from_utf8_lossy(&name[..length as usize]).into_owned();
+        let member = name.rsplit('.').next().unwrap().trim_end_matches("[0]");
+        match layout.iter().find(|(expected, _)| expected == member) {
+            Some((_, expected)) if *expected as GLint == offset => (),
+            Some((_, expected)) =>
+                return Err(format!("Uniform {name} is at offset {offset}, the uniform buffer holds it at {expected}")),
+            None => return Err(format!("Uniform block has a member {name} the uniform buffer does not hold"))
+        }
     }
+    Ok(())
+}
 
-    verify_opengl_version();
-    verify_spirv_extension_support();
-    // verify_spirv_support(); // FIXME: for some reason SHADER_BINARY_FORMATS is empty even tho SPIR_V is accepted...
 
-    let (wgsize, uniform_data) = match params.backend_type {
-        crate::parameters::BackendType::OpenglSpirv => crate::shaders::provider::get_spirv_configuration(&params),
-        crate::parameters::BackendType::OpenglSpirvU8 => crate::shaders::provider::get_spirv_configuration_u8(&params),
-        _ => panic!("Invalid backend for OpenGL")
-    };
+// specializes the constants the module declares out of the given ones
+unsafe fn compile_spirv_program(module: &[u32], constants: &[(u32, u32)]) -> GLuint {
+    let shader = gl::CreateShader(gl::COMPUTE_SHADER);
+    verify_error();
 
-    let program = compile_program(params.backend_type == crate::parameters::BackendType::OpenglSpirvU8);
+    let shader_binary: &[u8] = bytemuck::cast_slice(module);
+    gl::ShaderBinary(1, &shader as *const GLuint, gl::SHADER_BINARY_FORMAT_SPIR_V_ARB, shader_binary.as_ptr() as *const GLvoid, shader_binary.len() as GLsizei);
+    verify_error();
 
-    let mut uniform_buffer: GLuint = 0;
-    gl::CreateBuffers(1, &mut uniform_buffer as *mut GLuint);
+    // constants missing in the module are an error
+    let declared = crate::shaders::spirv::spec_constant_ids(module);
+    let (indices, values): (Vec<GLuint>, Vec<GLuint>) = constants.iter()
+        .filter(|(id, _)| declared.contains(id))
+        .copied()
+        .unzip();
+    let entry_point_name = std::ffi::CString::new("main").unwrap();
+    gl::SpecializeShader(shader, entry_point_name.as_ptr(), indices.len() as GLuint, indices.as_ptr(), values.as_ptr());
     verify_error();
 
-    gl::NamedBufferStorage(
-        uniform_buffer,
-        uniform_data.len() as GLsizeiptr,
-        uniform_data.as_ptr() as *const GLvoid,
-        0
-    );
+    verify_compiled(shader);
+    link_program(&[shader])
+}
+
+
+pub unsafe fn compile_glsl_shader(shader_type: GLenum, source: &str) -> GLuint {
+    let shader = gl::CreateShader(shader_type);
+    verify_error();
+
+    let source_ptr = source.as_ptr() as *const GLchar;
+    let source_length = source.len() as GLint;
+    gl::ShaderSource(shader, 1, &source_ptr as *const *const GLchar, &source_length as *const GLint);
+    verify_error();
+
+    gl::CompileShader(shader);
     verify_error();
 
-    let mut storage_buffer: GLuint = 0;
-    gl::CreateBuffers(1, &mut storage_buffer as *mut GLuint);
+    verify_compiled(shader);
+    shader
+}
+
+
+unsafe fn compile_glsl_program(source: &str) -> GLuint {
+    link_program(&[compile_glsl_shader(gl::COMPUTE_SHADER, source)])
+}
+
+
+unsafe fn compile_program(params: &Parameters) -> Result<GLuint, String> {
+    Ok(match params.backend_type {
+        BackendType::OpenglSpirv | BackendType::OpenglSpirvU8 => {
+            // workgroup size of the module becomes specializable like the constants it declares
+            let module = crate::shaders::spirv::add_workgroup_size_spec_constants(
+                &crate::shaders::provider::get_mandelbrot_spirv(params)
+            );
+            compile_spirv_program(&module, &crate::shaders::provider::get_spec_constants(params))
+        }
+        BackendType::OpenglGlsl => {
+            let program = compile_glsl_program(&crate::shaders::provider::get_mandelbrot_glsl(params)?);
+            verify_uniform_layout(program)?;
+            program
+        }
+        _ => panic!("Invalid backend for OpenGL")
+    })
+}
+
+
+// storage buffer of the given size which can be mapped for reading once the shaders wrote it
+unsafe fn create_readable_buffer(size: usize) -> GLuint {
+    let mut buffer: GLuint = 0;
+    gl::CreateBuffers(1, &mut buffer as *mut GLuint);
     verify_error();
 
-    let storage_buffer_size_bytes = std::mem::size_of::<u8>() * params.img_size_px as usize * params.img_size_px as usize;
     gl::NamedBufferStorage(
-        storage_buffer,
-        storage_buffer_size_bytes as GLsizeiptr,
+        buffer,
+        size as GLsizeiptr,
         std::ptr::null(),
         gl::MAP_READ_BIT
     );
     verify_error();
 
-    gl::BindBufferBase(gl::UNIFORM_BUFFER, 0, uniform_buffer);
-    verify_error();
-    gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 1, storage_buffer);
-    verify_error();
+    buffer
+}
+
 
-    gl::UseProgram(program);
+// copies `len` elements from the beginning of the buffer
+unsafe fn read_buffer<P: bytemuck::Pod>(buffer: GLuint, len: usize) -> Vec<P> {
+    let ptr = gl::MapNamedBuffer(buffer, gl::READ_ONLY);
     verify_error();
 
-    let init_time = start_time.elapsed();
+    let data = std::slice::from_raw_parts(ptr as *const P, len).into();
 
-    gl::DispatchCompute(wgsize.0, wgsize.1, wgsize.2);
-    verify_error();
+    if gl::UnmapNamedBuffer(buffer) != gl::TRUE {
+        tracing::warn!("Buffer unmapping failed");
+    }
+    data
+}
 
-    gl::MemoryBarrier(gl::CLIENT_MAPPED_BUFFER_BARRIER_BIT);
-    verify_error();
 
-    let fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
-    let fence_wait = gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, GLuint64::MAX);
-    verify_error();
-    match fence_wait {
-        gl::ALREADY_SIGNALED => (),
-        gl::TIMEOUT_EXPIRED => panic!("Timeout expired"),
-        gl::CONDITION_SATISFIED => (),
-        gl::WAIT_FAILED => panic!("Wait failed"),
-        _ => panic!("Unknown failure")
-    };
+// colorization program run after the mandelbrot one, with the colormap uploaded as its lookup table
+struct Colorize {
+    program: GLuint,
+    lut_buffer: GLuint
+}
 
-    let computation_time = start_time.elapsed() - init_time;
 
-    let storage_ptr = gl::MapNamedBuffer(storage_buffer, gl::READ_ONLY);
-    verify_error();
+impl Colorize {
+    unsafe fn new(backend_type: BackendType) -> Self {
+        // program is built the same way as the mandelbrot one, drivers of the GLSL backend may lack SPIR-V support
+        let program = match backend_type {
+            BackendType::OpenglGlsl => compile_glsl_program(&crate::shaders::provider::get_colorize_glsl()),
+            _ => compile_spirv_program(&crate::shaders::provider::get_colorize_spirv(), &[])
+        };
 
-    let data: Vec<u8> = std::slice::from_raw_parts(storage_ptr as *const u8, storage_buffer_size_bytes).into();
+        let lut = crate::colormap::inferno_lut();
+        let mut lut_buffer: GLuint = 0;
+        gl::CreateBuffers(1, &mut lut_buffer as *mut GLuint);
+        verify_error();
 
-    gl::DeleteSync(fence);
-    if gl::UnmapNamedBuffer(storage_buffer) != gl::TRUE {
-        tracing::warn!("Buffer unmapping failed");
+        gl::NamedBufferStorage(
+            lut_buffer,
+            lut.len() as GLsizeiptr,
+            lut.as_ptr() as *const GLvoid,
+            0
+        );
+        verify_error();
+
+        Colorize { program, lut_buffer }
+    }
+}
+
+
+// context and program kept alive between renders, only the uniform buffer is written for every render
+pub struct OpenglSession {
+    // dropped after the GL objects
+    _context: Context,
+    pub baked: crate::backends::session::BakedParameters,
+    program: GLuint,
+    uniform_buffer: GLuint,
+    // GL_TIME_ELAPSED query around the dispatch
+    time_query: GLuint,
+    // reallocated when the size of the image band changes, together with the buffer of colors
+    storage: Option<(GLuint, Option<GLuint>, usize)>,
+    colorize: Option<Colorize>
+}
+
+
+impl OpenglSession {
+    pub unsafe fn new(params: &Parameters) -> Result<Self, Box<dyn std::error::Error>> {
+        // GLSL backend needs only the direct state access of 4.5, SPIR-V shaders need 4.6
+        let gl_version = if params.backend_type == BackendType::OpenglGlsl { (4, 5) } else { (4, 6) };
+        let context = create_context(params, &[(Api::OpenGl, gl_version)])?;
+
+        if params.backend_type != BackendType::OpenglGlsl {
+            verify_spirv_extension_support();
+            // verify_spirv_support(); // FIXME: for some reason SHADER_BINARY_FORMATS is empty even tho SPIR_V is accepted...
+        }
+
+        let program = compile_program(params)?;
+
+        let uniform_data = crate::shaders::provider::get_uniform_data(params);
+        let mut uniform_buffer: GLuint = 0;
+        gl::CreateBuffers(1, &mut uniform_buffer as *mut GLuint);
+        verify_error();
+
+        gl::NamedBufferStorage(
+            uniform_buffer,
+            uniform_data.len() as GLsizeiptr,
+            std::ptr::null(),
+            gl::DYNAMIC_STORAGE_BIT
+        );
+        verify_error();
+
+        let mut time_query: GLuint = 0;
+        gl::CreateQueries(gl::TIME_ELAPSED, 1, &mut time_query as *mut GLuint);
+        verify_error();
+
+        let colorize = params.gpu_colorize.then(|| Colorize::new(params.backend_type));
+
+        Ok(OpenglSession {
+            _context: context,
+            baked: crate::backends::session::BakedParameters::new(params),
+            program,
+            uniform_buffer,
+            time_query,
+            storage: None,
+            colorize
+        })
+    }
+
+    unsafe fn reserve_storage(&mut self, size: usize) -> (GLuint, Option<GLuint>) {
+        match self.storage {
+            Some((storage_buffer, colors_buffer, storage_size)) if storage_size == size => return (storage_buffer, colors_buffer),
+            Some((storage_buffer, colors_buffer, _)) => {
+                gl::DeleteBuffers(1, &storage_buffer as *const GLuint);
+                if let Some(colors_buffer) = colors_buffer {
+                    gl::DeleteBuffers(1, &colors_buffer as *const GLuint);
+                }
+            },
+            None => ()
+        }
+
+        let storage_buffer = create_readable_buffer(size);
+        // colors take 4 bytes per pixel
+        let colors_buffer = self.colorize.is_some().then(|| create_readable_buffer(4 * size));
+
+        self.storage = Some((storage_buffer, colors_buffer, size));
+        (storage_buffer, colors_buffer)
+    }
+
+    pub unsafe fn render(&mut self, params: &Parameters) -> Result<crate::result::ComputeResult, Box<dyn std::error::Error>> {
+        self.baked.check(params)?;
+        let start_time = std::time::Instant::now();
+
+        let (wgsize, uniform_data) = crate::shaders::provider::get_configuration(params);
+        gl::NamedBufferSubData(
+            self.uniform_buffer,
+            0,
+            uniform_data.len() as GLsizeiptr,
+            uniform_data.as_ptr() as *const GLvoid
+        );
+        verify_error();
+
+        let storage_buffer_size_bytes = std::mem::size_of::<u8>() * params.img_size_px as usize * crate::shaders::provider::dispatched_rows(params);
+        let (storage_buffer, colors_buffer) = self.reserve_storage(storage_buffer_size_bytes);
+
+        gl::BindBufferBase(gl::UNIFORM_BUFFER, 0, self.uniform_buffer);
+        verify_error();
+        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 1, storage_buffer);
+        verify_error();
+
+        gl::UseProgram(self.program);
+        verify_error();
+
+        let init_time = start_time.elapsed();
+
+        gl::BeginQuery(gl::TIME_ELAPSED, self.time_query);
+        verify_error();
+
+        gl::DispatchCompute(wgsize.0, wgsize.1, wgsize.2);
+        verify_error();
+
+        if let (Some(colorize), Some(colors_buffer)) = (&self.colorize, colors_buffer) {
+            gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
+            verify_error();
+
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, colorize.lut_buffer);
+            verify_error();
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 2, colors_buffer);
+            verify_error();
+
+            gl::UseProgram(colorize.program);
+            verify_error();
+
+            let colorize_size = crate::shaders::provider::get_colorize_dispatch(params);
+            gl::DispatchCompute(colorize_size.0, colorize_size.1, colorize_size.2);
+            verify_error();
+        }
+
+        gl::EndQuery(gl::TIME_ELAPSED);
+        verify_error();
+
+        gl::MemoryBarrier(gl::CLIENT_MAPPED_BUFFER_BARRIER_BIT);
+        verify_error();
+
+        let fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        let fence_wait = gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, GLuint64::MAX);
+        verify_error();
+        match fence_wait {
+            gl::ALREADY_SIGNALED => (),
+            gl::TIMEOUT_EXPIRED => panic!("Timeout expired"),
+            gl::CONDITION_SATISFIED => (),
+            gl::WAIT_FAILED => panic!("Wait failed"),
+            _ => panic!("Unknown failure")
+        };
+
+        // result of the query is available once the fence is signaled
+        let mut elapsed_ns: GLuint64 = 0;
+        gl::GetQueryObjectui64v(self.time_query, gl::QUERY_RESULT, &mut elapsed_ns as *mut GLuint64);
+        verify_error();
+        let computation_time = std::time::Duration::from_nanos(elapsed_ns).min(start_time.elapsed() - init_time);
+
+        // rows of the last workgroup past the requested ones are dropped
+        let pixels = params.img_size_px as usize * params.rows.len();
+        let data = read_buffer(storage_buffer, pixels);
+        let colors = colors_buffer.map(|colors_buffer| read_buffer(colors_buffer, pixels));
+
+        gl::DeleteSync(fence);
+
+        Ok(crate::result::ComputeResult {
+            data,
+            colors,
+            costs: None,
+            initialization_time: init_time,
+            computation_time,
+            computation_time_estimated: false,
+            data_fetch_time: start_time.elapsed() - computation_time - init_time
+        })
+    }
+}
+
+
+impl Drop for OpenglSession {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some((storage_buffer, colors_buffer, _)) = self.storage {
+                gl::DeleteBuffers(1, &storage_buffer as *const GLuint);
+                if let Some(colors_buffer) = colors_buffer {
+                    gl::DeleteBuffers(1, &colors_buffer as *const GLuint);
+                }
+            }
+            if let Some(colorize) = &self.colorize {
+                gl::DeleteBuffers(1, &colorize.lut_buffer as *const GLuint);
+                gl::DeleteProgram(colorize.program);
+            }
+            gl::DeleteBuffers(1, &self.uniform_buffer as *const GLuint);
+            gl::DeleteQueries(1, &self.time_query as *const GLuint);
+            gl::DeleteProgram(self.program);
+        }
     }
-    gl::DeleteBuffers(1, &storage_buffer as *const GLuint);
-    gl::DeleteBuffers(1, &uniform_buffer as *const GLuint);
-    gl::DeleteProgram(program);
-
-    return crate::result::ComputeResult {
-        data,
-        initialization_time: init_time,
-        computation_time,
-        data_fetch_time: start_time.elapsed() - computation_time - init_time
-    };
 }
@@ -0,0 +1,234 @@
+use crate::backends::egl::{Api, Context};
+use crate::backends::opengl::{compile_glsl_shader, create_context, link_program, verify_error, verify_uniform_layout};
+use crate::gl;
+use crate::parameters::Parameters;
+
+use gl::types::*;
+
+
+// set rendered by a full-screen triangle into an RGBA8 texture, for GL 3.3 and GLES 3.0 class devices without compute
+// shaders; every texel holds 4 consecutive pixels, the same bytes a compute shader writes
+pub struct OpenglFragmentSession {
+    // dropped after the GL objects
+    _context: Context,
+    pub baked: crate::backends::session::BakedParameters,
+    program: GLuint,
+    vertex_array: GLuint,
+    uniform_buffer: GLuint,
+    framebuffer: GLuint,
+    // texture attached to the framebuffer, reallocated when the size of the strip changes
+    target: Option<(GLuint, GLsizei, GLsizei)>,
+    // GL_TIME_ELAPSED query around the draw, GLES has none
+    time_query: Option<GLuint>,
+    // rows rendered at once, limited by the texture and viewport sizes
+    max_strip_rows: u16
+}
+
+
+impl OpenglFragmentSession {
+    pub unsafe fn new(params: &Parameters) -> Result<Self, Box<dyn std::error::Error>> {
+        let context = create_context(params, &[(Api::OpenGl, (3, 3)), (Api::OpenGlEs, (3, 0))])?;
+        let es = context.api == Api::OpenGlEs;
+
+        let (vertex_source, fragment_source, uniform_block) = crate::shaders::provider::get_mandelbrot_fragment_glsl(params, es)?;
+        let program = link_program(&[
+            compile_glsl_shader(gl::VERTEX_SHADER, &vertex_source),
+            compile_glsl_shader(gl::FRAGMENT_SHADER, &fragment_source)
+        ]);
+        verify_uniform_layout(program)?;
+
+        // GLSL 3.30 has no explicit bindings, the block is bound by its name
+        let uniform_block = std::ffi::CString::new(uniform_block).unwrap();
+        let block_index = gl::GetUniformBlockIndex(program, uniform_block.as_ptr());
+        verify_error();
+        gl::UniformBlockBinding(program, block_index, 0);
+        verify_error();
+
+        // core profile draws only with a vertex array bound, even without any attributes
+        let mut vertex_array: GLuint = 0;
+        gl::GenVertexArrays(1, &mut vertex_array as *mut GLuint);
+        verify_error();
+
+        let uniform_data = crate::shaders::provider::get_uniform_data(params);
+        let mut uniform_buffer: GLuint = 0;
+        gl::GenBuffers(1, &mut uniform_buffer as *mut GLuint);
+        verify_error();
+        gl::BindBuffer(gl::UNIFORM_BUFFER, uniform_buffer);
+        verify_error();
+        gl::BufferData(gl::UNIFORM_BUFFER, uniform_data.len() as GLsizeiptr, std::ptr::null(), gl::DYNAMIC_DRAW);
+        verify_error();
+
+        let mut framebuffer: GLuint = 0;
+        gl::GenFramebuffers(1, &mut framebuffer as *mut GLuint);
+        verify_error();
+
+        let time_query = (!es).then(|| {
+            let mut time_query: GLuint = 0;
+            gl::GenQueries(1, &mut time_query as *mut GLuint);
+            verify_error();
+            time_query
+        });
+
+        let mut max_texture_size: GLint = 0;
+        gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_texture_size as *mut GLint);
+        let mut max_viewport_dims: [GLint; 2] = [0; 2];
+        gl::GetIntegerv(gl::MAX_VIEWPORT_DIMS, max_viewport_dims.as_mut_ptr());
+        verify_error();
+        let max_width = max_texture_size.min(max_viewport_dims[0]);
+        if params.img_size_px as GLint / 4 > max_width {
+            return Err(format!("Image is too wide for the device, which renders at most {} pixels per row", max_width * 4).into());
+        }
+        let max_strip_rows = max_texture_size.min(max_viewport_dims[1]).min(u16::MAX as GLint) as u16;
+
+        Ok(OpenglFragmentSession {
+            _context: context,
+            baked: crate::backends::session::BakedParameters::new(params),
+            program,
+            vertex_array,
+            uniform_buffer,
+            framebuffer,
+            target: None,
+            time_query,
+            max_strip_rows
+        })
+    }
+
+    unsafe fn reserve_target(&mut self, width: GLsizei, height: GLsizei) -> Result<(), String> {
+        match self.target {
+            Some((_, target_width, target_height)) if (target_width, target_height) == (width, height) => return Ok(()),
+            Some((texture, _, _)) => gl::DeleteTextures(1, &texture as *const GLuint),
+            None => ()
+        }
+
+        let mut texture: GLuint = 0;
+        gl::GenTextures(1, &mut texture as *mut GLuint);
+        verify_error();
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        verify_error();
+        gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA8 as GLint, width, height, 0, gl::RGBA, gl::UNSIGNED_BYTE, std::ptr::null());
+        verify_error();
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+        verify_error();
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        verify_error();
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture, 0);
+        verify_error();
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            gl::DeleteTextures(1, &texture as *const GLuint);
+            self.target = None;
+            return Err(format!("Framebuffer with a {width}x{height} RGBA8 texture is incomplete (status {status:#x})"));
+        }
+
+        self.target = Some((texture, width, height));
+        Ok(())
+    }
+
+    pub unsafe fn render(&mut self, params: &Parameters) -> Result<crate::result::ComputeResult, Box<dyn std::error::Error>> {
+        self.baked.check(params)?;
+        let start_time = std::time::Instant::now();
+        let mut init_time = std::time::Duration::ZERO;
+        let mut computation_time = std::time::Duration::ZERO;
+
+        let width = params.img_size_px as GLsizei / 4;
+        let mut data = vec![0u8; params.img_size_px as usize * params.rows.len()];
+        let max_strip_rows = self.max_strip_rows;
+        let strips = params.rows.clone().step_by(max_strip_rows as usize)
+            .map(|start| start..params.rows.end.min(start.saturating_add(max_strip_rows)));
+        for strip in strips {
+            let strip_start_time = std::time::Instant::now();
+            let strip_params = Parameters { rows: strip.clone(), ..params.clone() };
+            let uniform_data = crate::shaders::provider::get_uniform_data(&strip_params);
+            gl::BindBuffer(gl::UNIFORM_BUFFER, self.uniform_buffer);
+            verify_error();
+            gl::BufferSubData(gl::UNIFORM_BUFFER, 0, uniform_data.len() as GLsizeiptr, uniform_data.as_ptr() as *const GLvoid);
+            verify_error();
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, 0, self.uniform_buffer);
+            verify_error();
+
+            let height = strip.len() as GLsizei;
+            self.reserve_target(width, height)?;
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+            verify_error();
+            gl::Viewport(0, 0, width, height);
+            verify_error();
+
+            gl::UseProgram(self.program);
+            verify_error();
+            gl::BindVertexArray(self.vertex_array);
+            verify_error();
+            init_time += strip_start_time.elapsed();
+
+            let draw_start_time = std::time::Instant::now();
+            if let Some(time_query) = self.time_query {
+                gl::BeginQuery(gl::TIME_ELAPSED, time_query);
+                verify_error();
+            }
+
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            verify_error();
+
+            if self.time_query.is_some() {
+                gl::EndQuery(gl::TIME_ELAPSED);
+                verify_error();
+            }
+
+            let fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+            let fence_wait = gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, GLuint64::MAX);
+            verify_error();
+            match fence_wait {
+                gl::ALREADY_SIGNALED => (),
+                gl::TIMEOUT_EXPIRED => panic!("Timeout expired"),
+                gl::CONDITION_SATISFIED => (),
+                gl::WAIT_FAILED => panic!("Wait failed"),
+                _ => panic!("Unknown failure")
+            };
+            gl::DeleteSync(fence);
+            let wait_time = draw_start_time.elapsed();
+
+            computation_time += match self.time_query {
+                Some(time_query) => {
+                    let mut elapsed_ns: GLuint64 = 0;
+                    gl::GetQueryObjectui64v(time_query, gl::QUERY_RESULT, &mut elapsed_ns as *mut GLuint64);
+                    verify_error();
+                    std::time::Duration::from_nanos(elapsed_ns).min(wait_time)
+                },
+                None => wait_time
+            };
+
+            // rows of the strip follow each other in the data as rows of the texture do
+            let offset = (strip.start - params.rows.start) as usize * params.img_size_px as usize;
+            gl::ReadPixels(0, 0, width, height, gl::RGBA, gl::UNSIGNED_BYTE, data[offset..].as_mut_ptr() as *mut GLvoid);
+            verify_error();
+        }
+
+        Ok(crate::result::ComputeResult {
+            data,
+            colors: None,
+            costs: None,
+            initialization_time: init_time,
+            computation_time,
+            computation_time_estimated: self.time_query.is_none(),
+            data_fetch_time: start_time.elapsed() - computation_time - init_time
+        })
+    }
+}
+
+
+impl Drop for OpenglFragmentSession {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some((texture, _, _)) = self.target {
+                gl::DeleteTextures(1, &texture as *const GLuint);
+            }
+            if let Some(time_query) = self.time_query {
+                gl::DeleteQueries(1, &time_query as *const GLuint);
+            }
+            gl::DeleteFramebuffers(1, &self.framebuffer as *const GLuint);
+            gl::DeleteBuffers(1, &self.uniform_buffer as *const GLuint);
+            gl::DeleteVertexArrays(1, &self.vertex_array as *const GLuint);
+            gl::DeleteProgram(self.program);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::backends::opengl::OpenglSession;
+use crate::backends::opengl_fragment::OpenglFragmentSession;
+use crate::backends::wgpu::WgpuSession;
+use crate::parameters::{BackendType, Parameters};
+use crate::result::ComputeResult;
+
+use std::sync::Arc;
+
+
+// parameters compiled into the pipelines of a session, it renders only with the same ones
+pub struct BakedParameters {
+    backend_type: BackendType,
+    workgroup_size: [u16; 2],
+    pixels_per_invocation: u16,
+    sample_count: usize,
+    gpu_colorize: bool,
+    shader: Option<Arc<crate::shaders::loader::Shader>>
+}
+
+
+impl BakedParameters {
+    pub fn new(params: &Parameters) -> Self {
+        BakedParameters {
+            backend_type: params.backend_type,
+            workgroup_size: params.workgroup_size,
+            pixels_per_invocation: params.pixels_per_invocation,
+            sample_count: params.sampling.offsets.len(),
+            gpu_colorize: params.gpu_colorize,
+            shader: params.shader.clone()
+        }
+    }
+
+    pub fn check(&self, params: &Parameters) -> Result<(), String> {
+        // a reloaded shader is another one, even with the same source
+        let same_shader = match (&self.shader, &params.shader) {
+            (Some(baked), Some(shader)) => Arc::ptr_eq(baked, shader),
+            (baked, shader) => baked.is_none() && shader.is_none()
+        };
+        let differing: Vec<&str> = [
+            ("backend", self.backend_type != params.backend_type),
+            ("workgroup size", self.workgroup_size != params.workgroup_size),
+            ("pixels per invocation", self.pixels_per_invocation != params.pixels_per_invocation),
+            ("sample count", self.sample_count != params.sampling.offsets.len()),
+            ("colorization", self.gpu_colorize != params.gpu_colorize),
+            ("shader", !same_shader)
+        ].into_iter().filter_map(|(name, differs)| differs.then_some(name)).collect();
+        if differing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Session was built with another {}", differing.join(", ")))
+        }
+    }
+}
+
+
+// GPU backend set up once and rendering any number of images, e.g. frames of an animation or bands of the hybrid
+// backend
+#[allow(clippy::large_enum_variant)] // there is one session per device, its size does not matter
+pub enum GpuSession {
+    Opengl(OpenglSession),
+    OpenglFragment(OpenglFragmentSession),
+    Wgpu(WgpuSession)
+}
+
+
+impl GpuSession {
+    pub fn new(params: &Parameters) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(match params.backend_type {
+            BackendType::OpenglSpirv | BackendType::OpenglSpirvU8 | BackendType::OpenglGlsl =>
+                GpuSession::Opengl(unsafe { OpenglSession::new(params)? }),
+            BackendType::OpenglFragment =>
+                GpuSession::OpenglFragment(unsafe { OpenglFragmentSession::new(params)? }),
+            BackendType::WgpuSpirv | BackendType::WgpuWgsl =>
+                GpuSession::Wgpu(pollster::block_on(WgpuSession::new(params))?),
+            backend_type => panic!("{backend_type:?} is not a GPU backend")
+        })
+    }
+
+    pub fn baked(&self) -> &BakedParameters {
+        match self {
+            GpuSession::Opengl(session) => &session.baked,
+            GpuSession::OpenglFragment(session) => &session.baked,
+            GpuSession::Wgpu(session) => &session.baked
+        }
+    }
+
+    pub fn render(&mut self, params: &Parameters) -> Result<ComputeResult, Box<dyn std::error::Error>> {
+        match self {
+            GpuSession::Opengl(session) => unsafe { session.render(params) },
+            GpuSession::OpenglFragment(session) => unsafe { session.render(params) },
+            GpuSession::Wgpu(session) => pollster::block_on(session.render(params))
+        }
+    }
+}
+
+
+// builds the session kept between renders again when the parameters need other pipelines
+pub fn prepare(session: &mut Option<GpuSession>, params: &Parameters) -> Result<(), Box<dyn std::error::Error>> {
+    if session.as_ref().is_none_or(|session| session.baked().check(params).is_err()) {
+        // the previous context is released before the next one is created
+        *session = None;
+        *session = Some(GpuSession::new(params)?);
+    }
+    Ok(())
+}
+
+
+pub fn render_reusing(session: &mut Option<GpuSession>, params: &Parameters) -> Result<ComputeResult, Box<dyn std::error::Error>> {
+    let start_time = std::time::Instant::now();
+    prepare(session, params)?;
+    let session_time = start_time.elapsed();
+
+    let result = session.as_mut().unwrap().render(params)?;
+    Ok(ComputeResult {
+        initialization_time: session_time + result.initialization_time,
+        ..result
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn rejects_parameters_baked_otherwise() {
+        let params = crate::parameters::parse_params(&["opengl-glsl", "--", "256", "255", "-2", "1", "-1.5", "1.5"]).unwrap();
+        let baked = BakedParameters::new(&params);
+        assert!(baked.check(&Parameters { max_iter: 1000, rows: 16..32, ..params.clone() }).is_ok());
+
+        let other = Parameters { workgroup_size: [8, 8], gpu_colorize: !params.gpu_colorize, ..params.clone() };
+        assert_eq!(baked.check(&other), Err("Session was built with another workgroup size, colorization".to_string()));
+        let other = Parameters { backend_type: BackendType::OpenglSpirv, ..params };
+        assert_eq!(baked.check(&other), Err("Session was built with another backend".to_string()));
+    }
+}
@@ -0,0 +1,444 @@
+use crate::backends::cpu::{average_samples, iterations_to_value, mix, with_thread_pool};
+use crate::parameters::{InteriorChecks, Parameters};
+
+use rayon::prelude::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+
+#[derive(Copy, Clone, Debug)]
+pub enum SimdLevel {
+    // architectures other than x86_64
+    #[cfg_attr(target_arch = "x86_64", allow(dead_code))]
+    Scalar,
+    Sse2,
+    Avx2,
+    Avx512
+}
+
+
+impl SimdLevel {
+    pub fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx512f") {
+                return SimdLevel::Avx512;
+            }
+            if is_x86_feature_detected!("avx2") {
+                return SimdLevel::Avx2;
+            }
+            SimdLevel::Sse2
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        SimdLevel::Scalar
+    }
+
+    pub fn lanes(&self) -> usize {
+        match self {
+            SimdLevel::Scalar => 1,
+            SimdLevel::Sse2 => 4,
+            SimdLevel::Avx2 => 8,
+            SimdLevel::Avx512 => 16
+        }
+    }
+}
+
+
+// widest vector holds 16 lanes of f32
+const MAX_LANES: usize = 16;
+
+
+// vector of f32 lanes, with lane masks and per-lane u32 counters, operations are IEEE single precision like the
+// scalar ones so that every lane computes exactly what the scalar kernel does
+trait Lanes: Copy {
+    const LANES: usize;
+    type Mask: Copy;
+    type Counter: Copy;
+
+    unsafe fn splat(v: f32) -> Self;
+    // first LANES values of the slice
+    unsafe fn load(v: &[f32]) -> Self;
+    unsafe fn add(self, other: Self) -> Self;
+    unsafe fn sub(self, other: Self) -> Self;
+    unsafe fn mul(self, other: Self) -> Self;
+    unsafe fn gt(self, other: Self) -> Self::Mask;
+    unsafe fn le(self, other: Self) -> Self::Mask;
+    unsafe fn eq(self, other: Self) -> Self::Mask;
+
+    unsafe fn mask_all() -> Self::Mask;
+    unsafe fn mask_none() -> Self::Mask;
+    unsafe fn and(a: Self::Mask, b: Self::Mask) -> Self::Mask;
+    // a and not b
+    unsafe fn and_not(a: Self::Mask, b: Self::Mask) -> Self::Mask;
+    unsafe fn or(a: Self::Mask, b: Self::Mask) -> Self::Mask;
+    unsafe fn any(mask: Self::Mask) -> bool;
+
+    unsafe fn counter_zero() -> Self::Counter;
+    // adds 1 to the lanes of the mask
+    unsafe fn increment(counter: Self::Counter, mask: Self::Mask) -> Self::Counter;
+    // zeroes the lanes of the mask
+    unsafe fn clear(counter: Self::Counter, mask: Self::Mask) -> Self::Counter;
+    // first LANES values of the slice
+    unsafe fn store(counter: Self::Counter, out: &mut [u32]);
+}
+
+
+// one lane, for architectures without the vector implementations
+#[derive(Clone, Copy)]
+struct Scalar(f32);
+
+
+impl Lanes for Scalar {
+    const LANES: usize = 1;
+    type Mask = bool;
+    type Counter = u32;
+
+    #[inline(always)] unsafe fn splat(v: f32) -> Self { Scalar(v) }
+    #[inline(always)] unsafe fn load(v: &[f32]) -> Self { Scalar(v[0]) }
+    #[inline(always)] unsafe fn add(self, other: Self) -> Self { Scalar(self.0 + other.0) }
+    #[inline(always)] unsafe fn sub(self, other: Self) -> Self { Scalar(self.0 - other.0) }
+    #[inline(always)] unsafe fn mul(self, other: Self) -> Self { Scalar(self.0 * other.0) }
+    #[inline(always)] unsafe fn gt(self, other: Self) -> bool { self.0 > other.0 }
+    #[inline(always)] unsafe fn le(self, other: Self) -> bool { self.0 <= other.0 }
+    #[inline(always)] unsafe fn eq(self, other: Self) -> bool { self.0 == other.0 }
+
+    #[inline(always)] unsafe fn mask_all() -> bool { true }
+    #[inline(always)] unsafe fn mask_none() -> bool { false }
+    #[inline(always)] unsafe fn and(a: bool, b: bool) -> bool { a && b }
+    #[inline(always)] unsafe fn and_not(a: bool, b: bool) -> bool { a && !b }
+    #[inline(always)] unsafe fn or(a: bool, b: bool) -> bool { a || b }
+    #[inline(always)] unsafe fn any(mask: bool) -> bool { mask }
+
+    #[inline(always)] unsafe fn counter_zero() -> u32 { 0 }
+    #[inline(always)] unsafe fn increment(counter: u32, mask: bool) -> u32 { counter + mask as u32 }
+    #[inline(always)] unsafe fn clear(counter: u32, mask: bool) -> u32 { if mask { 0 } else { counter } }
+    #[inline(always)] unsafe fn store(counter: u32, out: &mut [u32]) { out[0] = counter; }
+}
+
+
+// masks are lanes with all bits set, subtracting them from the counters adds 1
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Copy)]
+struct Sse2(std::arch::x86_64::__m128);
+
+
+#[cfg(target_arch = "x86_64")]
+impl Lanes for Sse2 {
+    const LANES: usize = 4;
+    type Mask = std::arch::x86_64::__m128;
+    type Counter = std::arch::x86_64::__m128i;
+
+    #[inline(always)] unsafe fn splat(v: f32) -> Self { Sse2(_mm_set1_ps(v)) }
+    #[inline(always)] unsafe fn load(v: &[f32]) -> Self { Sse2(_mm_loadu_ps(v[..4].as_ptr())) }
+    #[inline(always)] unsafe fn add(self, other: Self) -> Self { Sse2(_mm_add_ps(self.0, other.0)) }
+    #[inline(always)] unsafe fn sub(self, other: Self) -> Self { Sse2(_mm_sub_ps(self.0, other.0)) }
+    #[inline(always)] unsafe fn mul(self, other: Self) -> Self { Sse2(_mm_mul_ps(self.0, other.0)) }
+    #[inline(always)] unsafe fn gt(self, other: Self) -> __m128 { _mm_cmpgt_ps(self.0, other.0) }
+    #[inline(always)] unsafe fn le(self, other: Self) -> __m128 { _mm_cmple_ps(self.0, other.0) }
+    #[inline(always)] unsafe fn eq(self, other: Self) -> __m128 { _mm_cmpeq_ps(self.0, other.0) }
+
+    #[inline(always)] unsafe fn mask_all() -> __m128 { _mm_castsi128_ps(_mm_set1_epi32(-1)) }
+    #[inline(always)] unsafe fn mask_none() -> __m128 { _mm_setzero_ps() }
+    #[inline(always)] unsafe fn and(a: __m128, b: __m128) -> __m128 { _mm_and_ps(a, b) }
+    #[inline(always)] unsafe fn and_not(a: __m128, b: __m128) -> __m128 { _mm_andnot_ps(b, a) }
+    #[inline(always)] unsafe fn or(a: __m128, b: __m128) -> __m128 { _mm_or_ps(a, b) }
+    #[inline(always)] unsafe fn any(mask: __m128) -> bool { _mm_movemask_ps(mask) != 0 }
+
+    #[inline(always)] unsafe fn counter_zero() -> __m128i { _mm_setzero_si128() }
+    #[inline(always)] unsafe fn increment(counter: __m128i, mask: __m128) -> __m128i {
+        _mm_sub_epi32(counter, _mm_castps_si128(mask))
+    }
+    #[inline(always)] unsafe fn clear(counter: __m128i, mask: __m128) -> __m128i {
+        _mm_andnot_si128(_mm_castps_si128(mask), counter)
+    }
+    #[inline(always)] unsafe fn store(counter: __m128i, out: &mut [u32]) {
+        _mm_storeu_si128(out[..4].as_mut_ptr() as *mut __m128i, counter)
+    }
+}
+
+
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Copy)]
+struct Avx2(std::arch::x86_64::__m256);
+
+
+#[cfg(target_arch = "x86_64")]
+impl Lanes for Avx2 {
+    const LANES: usize = 8;
+    type Mask = std::arch::x86_64::__m256;
+    type Counter = std::arch::x86_64::__m256i;
+
+    #[inline(always)] unsafe fn splat(v: f32) -> Self { Avx2(_mm256_set1_ps(v)) }
+    #[inline(always)] unsafe fn load(v: &[f32]) -> Self { Avx2(_mm256_loadu_ps(v[..8].as_ptr())) }
+    #[inline(always)] unsafe fn add(self, other: Self) -> Self { Avx2(_mm256_add_ps(self.0, other.0)) }
+    #[inline(always)] unsafe fn sub(self, other: Self) -> Self { Avx2(_mm256_sub_ps(self.0, other.0)) }
+    #[inline(always)] unsafe fn mul(self, other: Self) -> Self { Avx2(_mm256_mul_ps(self.0, other.0)) }
+    #[inline(always)] unsafe fn gt(self, other: Self) -> __m256 { _mm256_cmp_ps::<_CMP_GT_OQ>(self.0, other.0) }
+    #[inline(always)] unsafe fn le(self, other: Self) -> __m256 { _mm256_cmp_ps::<_CMP_LE_OQ>(self.0, other.0) }
+    #[inline(always)] unsafe fn eq(self, other: Self) -> __m256 { _mm256_cmp_ps::<_CMP_EQ_OQ>(self.0, other.0) }
+
+    #[inline(always)] unsafe fn mask_all() -> __m256 { _mm256_castsi256_ps(_mm256_set1_epi32(-1)) }
+    #[inline(always)] unsafe fn mask_none() -> __m256 { _mm256_setzero_ps() }
+    #[inline(always)] unsafe fn and(a: __m256, b: __m256) -> __m256 { _mm256_and_ps(a, b) }
+    #[inline(always)] unsafe fn and_not(a: __m256, b: __m256) -> __m256 { _mm256_andnot_ps(b, a) }
+    #[inline(always)] unsafe fn or(a: __m256, b: __m256) -> __m256 { _mm256_or_ps(a, b) }
+    #[inline(always)] unsafe fn any(mask: __m256) -> bool { _mm256_movemask_ps(mask) != 0 }
+
+    #[inline(always)] unsafe fn counter_zero() -> __m256i { _mm256_setzero_si256() }
+    #[inline(always)] unsafe fn increment(counter: __m256i, mask: __m256) -> __m256i {
+        _mm256_sub_epi32(counter, _mm256_castps_si256(mask))
+    }
+    #[inline(always)] unsafe fn clear(counter: __m256i, mask: __m256) -> __m256i {
+        _mm256_andnot_si256(_mm256_castps_si256(mask), counter)
+    }
+    #[inline(always)] unsafe fn store(counter: __m256i, out: &mut [u32]) {
+        _mm256_storeu_si256(out[..8].as_mut_ptr() as *mut __m256i, counter)
+    }
+}
+
+
+// masks are bitmasks of the lanes
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Copy)]
+struct Avx512(std::arch::x86_64::__m512);
+
+
+#[cfg(target_arch = "x86_64")]
+impl Lanes for Avx512 {
+    const LANES: usize = 16;
+    type Mask = __mmask16;
+    type Counter = std::arch::x86_64::__m512i;
+
+    #[inline(always)] unsafe fn splat(v: f32) -> Self { Avx512(_mm512_set1_ps(v)) }
+    #[inline(always)] unsafe fn load(v: &[f32]) -> Self { Avx512(_mm512_loadu_ps(v[..16].as_ptr())) }
+    #[inline(always)] unsafe fn add(self, other: Self) -> Self { Avx512(_mm512_add_ps(self.0, other.0)) }
+    #[inline(always)] unsafe fn sub(self, other: Self) -> Self { Avx512(_mm512_sub_ps(self.0, other.0)) }
+    #[inline(always)] unsafe fn mul(self, other: Self) -> Self { Avx512(_mm512_mul_ps(self.0, other.0)) }
+    #[inline(always)] unsafe fn gt(self, other: Self) -> __mmask16 { _mm512_cmp_ps_mask::<_CMP_GT_OQ>(self.0, other.0) }
+    #[inline(always)] unsafe fn le(self, other: Self) -> __mmask16 { _mm512_cmp_ps_mask::<_CMP_LE_OQ>(self.0, other.0) }
+    #[inline(always)] unsafe fn eq(self, other: Self) -> __mmask16 { _mm512_cmp_ps_mask::<_CMP_EQ_OQ>(self.0, other.0) }
+
+    #[inline(always)] unsafe fn mask_all() -> __mmask16 { 0xffff }
+    #[inline(always)] unsafe fn mask_none() -> __mmask16 { 0 }
+    #[inline(always)] unsafe fn and(a: __mmask16, b: __mmask16) -> __mmask16 { a & b }
+    #[inline(always)] unsafe fn and_not(a: __mmask16, b: __mmask16) -> __mmask16 { a & !b }
+    #[inline(always)] unsafe fn or(a: __mmask16, b: __mmask16) -> __mmask16 { a | b }
+    #[inline(always)] unsafe fn any(mask: __mmask16) -> bool { mask != 0 }
+
+    #[inline(always)] unsafe fn counter_zero() -> __m512i { _mm512_setzero_si512() }
+    #[inline(always)] unsafe fn increment(counter: __m512i, mask: __mmask16) -> __m512i {
+        _mm512_mask_add_epi32(counter, mask, counter, _mm512_set1_epi32(1))
+    }
+    #[inline(always)] unsafe fn clear(counter: __m512i, mask: __mmask16) -> __m512i {
+        _mm512_maskz_mov_epi32(!mask, counter)
+    }
+    #[inline(always)] unsafe fn store(counter: __m512i, out: &mut [u32]) {
+        _mm512_storeu_si512(out[..16].as_mut_ptr() as *mut _, counter)
+    }
+}
+
+
+// same test as the scalar `inside_main_bulbs`, operation by operation
+#[inline(always)]
+unsafe fn inside_main_bulbs_lanes<V: Lanes>(x: V, y: V) -> V::Mask {
+    let quarter = V::splat(0.25);
+    let xq = x.sub(quarter);
+    let q = xq.mul(xq).add(y.mul(y));
+    let xb = x.add(V::splat(1.0));
+    V::or(
+        q.mul(q.add(xq)).le(quarter.mul(y).mul(y)),
+        xb.mul(xb).add(y.mul(y)).le(V::splat(0.0625))
+    )
+}
+
+
+// same as the scalar version, but evaluates all lanes at once. Every lane stops counting as soon as it escapes
+// or its orbit is found periodic, whole group stops iterating when none of its lanes is active.
+#[inline(always)]
+unsafe fn mandelbrot_lanes<V: Lanes>(cx: V, cy: V, max_iter: u32, checks: InteriorChecks) -> V::Counter {
+    let four = V::splat(4.0);
+    let mut zx = V::splat(0.0);
+    let mut zy = V::splat(0.0);
+    let mut active = V::mask_all();
+    let mut inside = V::mask_none();
+    let mut iterations = V::counter_zero();
+
+    if checks.bulbs {
+        inside = inside_main_bulbs_lanes(cx, cy);
+        active = V::and_not(active, inside);
+    }
+
+    // all lanes iterate in lockstep, so Brent's cycle detection schedule is shared
+    let mut saved_x = zx;
+    let mut saved_y = zy;
+    let mut period = 1u32;
+    let mut steps = 0u32;
+
+    for _ in 0..max_iter {
+        // if we got outside circle of radius 2 we will diverge to infinity
+        active = V::and_not(active, zx.mul(zx).add(zy.mul(zy)).gt(four));
+        if !V::any(active) {
+            break;
+        }
+        iterations = V::increment(iterations, active);
+
+        let x = zx.mul(zx).sub(zy.mul(zy)).add(cx);
+        zy = zx.mul(zy).add(zy.mul(zx)).add(cy);
+        zx = x;
+
+        if checks.periodicity {
+            let cycle = V::and(V::and(zx.eq(saved_x), zy.eq(saved_y)), active);
+            inside = V::or(inside, cycle);
+            active = V::and_not(active, cycle);
+            steps += 1;
+            if steps == period {
+                saved_x = zx;
+                saved_y = zy;
+                steps = 0;
+                period *= 2;
+            }
+        }
+    }
+
+    // points which never escaped are inside the set
+    V::clear(iterations, V::or(active, inside))
+}
+
+
+#[inline(always)]
+unsafe fn render_row<V: Lanes>(row: &mut [u8], y: usize, params: &Parameters) {
+    let img_size = params.img_size_px as f32;
+    let limits = params.limits.map(|v| v as f32);
+    let img_y = mix(limits[2], limits[3], y as f32 / img_size);
+
+    let row_len = row.len();
+    for (group_idx, group) in row.chunks_mut(V::LANES).enumerate() {
+        let mut img_x = [0.0f32; MAX_LANES];
+        for (l, x) in img_x[..V::LANES].iter_mut().enumerate() {
+            // lanes past the end of the row repeat the last pixel
+            let px = (group_idx * V::LANES + l).min(row_len - 1);
+            *x = mix(limits[0], limits[1], px as f32 / img_size);
+        }
+
+        let mut sum = [0u32; MAX_LANES];
+        for [offset_x, offset_y] in params.sampling.offsets.iter() {
+            let cy = V::splat(img_y + offset_y / img_size);
+            let mut cx = img_x;
+            cx.iter_mut().for_each(|x| *x += offset_x / img_size);
+            let mut iterations = [0u32; MAX_LANES];
+            V::store(mandelbrot_lanes(V::load(&cx), cy, params.budget(), params.interior_checks), &mut iterations);
+            for l in 0..V::LANES {
+                sum[l] += iterations[l];
+            }
+        }
+
+        for (pixel, sum) in group.iter_mut().zip(sum) {
+            *pixel = iterations_to_value(average_samples(sum, params), params);
+        }
+    }
+}
+
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f")]
+unsafe fn render_row_avx512(row: &mut [u8], y: usize, params: &Parameters) {
+    render_row::<Avx512>(row, y, params)
+}
+
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn render_row_avx2(row: &mut [u8], y: usize, params: &Parameters) {
+    render_row::<Avx2>(row, y, params)
+}
+
+
+#[cfg(target_arch = "x86_64")]
+fn render_row_sse2(row: &mut [u8], y: usize, params: &Parameters) {
+    // SSE2 is part of the x86_64 baseline
+    unsafe { render_row::<Sse2>(row, y, params) }
+}
+
+
+fn render_row_scalar(row: &mut [u8], y: usize, params: &Parameters) {
+    unsafe { render_row::<Scalar>(row, y, params) }
+}
+
+
+fn render_rows(params: &Parameters, level: SimdLevel) -> Vec<u8> {
+    let img_size_px = params.img_size_px as usize;
+    let mut data = vec![0u8; img_size_px * params.rows.len()];
+    with_thread_pool(params, || data.par_chunks_mut(img_size_px).enumerate().for_each(|(row_idx, row)| {
+        let y = params.rows.start as usize + row_idx;
+        match level {
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx512 => unsafe { render_row_avx512(row, y, params) },
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx2 => unsafe { render_row_avx2(row, y, params) },
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Sse2 => render_row_sse2(row, y, params),
+            _ => render_row_scalar(row, y, params)
+        }
+    }));
+    data
+}
+
+
+pub fn run_cpu_simd(params: &Parameters) -> crate::result::ComputeResult {
+    let start_time = std::time::Instant::now();
+    let level = SimdLevel::detect();
+    tracing::info!("SIMD level: {level:?} ({} lanes)", level.lanes());
+
+    let init_time = start_time.elapsed();
+
+    let data = render_rows(params, level);
+
+    crate::result::ComputeResult {
+        data,
+        colors: None,
+        costs: None,
+        initialization_time: init_time,
+        computation_time: start_time.elapsed() - init_time,
+        computation_time_estimated: false,
+        data_fetch_time: std::time::Duration::ZERO
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::parameters::parse_params;
+
+    use super::{render_rows, SimdLevel};
+
+
+    fn supported_levels() -> Vec<SimdLevel> {
+        let mut levels = vec![SimdLevel::Scalar];
+        #[cfg(target_arch = "x86_64")]
+        {
+            levels.push(SimdLevel::Sse2);
+            if is_x86_feature_detected!("avx2") {
+                levels.push(SimdLevel::Avx2);
+            }
+            if is_x86_feature_detected!("avx512f") {
+                levels.push(SimdLevel::Avx512);
+            }
+        }
+        levels
+    }
+
+
+    // every lane has to compute exactly what the scalar kernel of cpu-par-iter does, including rows which are not
+    // a multiple of the lanes
+    #[test]
+    fn matches_cpu_par_iter() {
+        for options in [&[][..], &["--bulb-check", "--periodicity-check"], &["--samples", "4", "--sample-pattern", "jittered"]] {
+            let args = [&["cpu-simd", "61", "200"][..], options, &["--", "-2.2", "0.8", "-1.5", "1.5"]].concat();
+            let params = parse_params(&args).unwrap();
+            let expected = crate::backends::cpu::run_cpu_par_iter(&params).data;
+            for level in supported_levels() {
+                assert!(render_rows(&params, level) == expected, "{level:?} with {options:?} differs from cpu-par-iter");
+            }
+        }
+    }
+}
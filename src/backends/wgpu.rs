@@ -1,128 +1,486 @@
-use crate::parameters::Parameters;
+use crate::parameters::{AdapterSelector, Parameters, WgpuBackend};
 
-use wgpu::util::DeviceExt;
+use std::sync::Mutex;
 
 
-pub async fn run_wgpu(params: &Parameters) -> crate::result::ComputeResult {
-    let start_time = std::time::Instant::now();
+// rows of a tile handed to an adapter at once when rendering on all of them
+const MULTI_ADAPTER_TILE_ROWS: u16 = 64;
 
-    // Instantiates instance of WebGPU
-    let instance = wgpu::Instance::new(wgpu::Backends::VULKAN);
+// largest part of the image copied to the staging buffer and mapped at once
+const READBACK_CHUNK_SIZE: usize = 16 << 20;
 
-    // `request_adapter` instantiates the general connection to the GPU
-    let adapter = instance
-        .request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
-            force_fallback_adapter: false,
-            compatible_surface: None
-        })
-        .await.unwrap();
 
-    let mut requested_limits = wgpu::Limits::default();
-    // requesting buffer binds of 256MB
-    requested_limits.max_storage_buffer_binding_size = 2 << 28;
+async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
+    // software and GL adapters do not reach the default limits, so the adapter's own are requested
+    let adapter_limits = adapter.limits();
+    let requested_limits = wgpu::Limits {
+        // requesting buffer binds of 256MB
+        max_storage_buffer_binding_size: (2 << 28).min(adapter_limits.max_storage_buffer_binding_size),
+        ..adapter_limits
+    };
 
     // `request_device` instantiates the feature specific connection to the GPU, defining some parameters,
     //  `features` being the available features.
-    let (device, queue) = adapter
+    adapter
         .request_device(
             &wgpu::DeviceDescriptor {
                 label: None,
-                features: wgpu::Features::empty(),
+                // device execution time is measured when the adapter can
+                features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
                 limits: requested_limits,
             },
             None,
         )
         .await
-        .unwrap();
+}
 
-    tracing::info!("Selected device: {:?}", adapter.get_info());
 
-    execute_gpu_inner(&device, &queue, params, start_time).await
+fn backends(params: &Parameters) -> wgpu::Backends {
+    match params.wgpu_backend {
+        WgpuBackend::Vulkan => wgpu::Backends::VULKAN,
+        WgpuBackend::Gl => wgpu::Backends::GL,
+        WgpuBackend::Any => wgpu::Backends::all()
+    }
 }
 
 
-async fn execute_gpu_inner(
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    params: &Parameters,
-    start_time: std::time::Instant
-) -> crate::result::ComputeResult {
-    let (wg_size, shader_module, mut uniform_buffer) = crate::shaders::provider::get_wgpu_shader(&params, &device);
+// adapters of the selected backends, only the software ones when the fallback adapter is forced
+fn enumerate_adapters(instance: &wgpu::Instance, params: &Parameters) -> Vec<wgpu::Adapter> {
+    let adapters: Vec<wgpu::Adapter> = instance.enumerate_adapters(backends(params))
+        .filter(|adapter| !params.force_fallback_adapter || adapter.get_info().device_type == wgpu::DeviceType::Cpu)
+        .collect();
+    for (idx, adapter) in adapters.iter().enumerate() {
+        tracing::debug!("Adapter {idx}: {:?}", adapter.get_info());
+    }
+    adapters
+}
 
-    if uniform_buffer.len() < 32 { uniform_buffer.resize(32, 0u8); }
-    let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: None,
-        contents: uniform_buffer.as_slice(),
-        usage: wgpu::BufferUsages::UNIFORM,
-    });
 
-    let storage_buffer_size: usize = std::mem::size_of::<u8>() * params.img_size_px as usize * params.img_size_px as usize;
-    let storage_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: None,
-        size: storage_buffer_size as wgpu::BufferAddress,
-        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::MAP_READ,
-        mapped_at_creation: false,
-    });
+async fn select_adapter(instance: &wgpu::Instance, params: &Parameters) -> Result<wgpu::Adapter, Box<dyn std::error::Error>> {
+    let selector = match &params.adapter {
+        Some(selector) => selector,
+        None => {
+            // `request_adapter` instantiates the general connection to the GPU
+            return instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    force_fallback_adapter: params.force_fallback_adapter,
+                    compatible_surface: None
+                })
+                .await.ok_or_else(|| "No adapter found".into());
+        }
+    };
 
-    let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: None,
-        layout: None,
-        module: &shader_module,
-        entry_point: "main",
-    });
+    let adapters = enumerate_adapters(instance, params);
+    let count = adapters.len();
+    let adapter = match selector {
+        AdapterSelector::Index(idx) => adapters.into_iter().nth(*idx),
+        AdapterSelector::Name(name) => adapters.into_iter().find(|a| a.get_info().name.to_lowercase().contains(name))
+    };
+    adapter.ok_or_else(|| format!("No adapter matching {selector:?} among {count} adapters").into())
+}
 
-    let bind_group_layout = compute_pipeline.get_bind_group_layout(0);
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &bind_group_layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: uniform_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: storage_buffer.as_entire_binding(),
-            },
-        ],
-    });
 
-    let mut encoder =
-        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-    {
-        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
-        cpass.set_pipeline(&compute_pipeline);
-        cpass.set_bind_group(0, &bind_group, &[]);
-        cpass.insert_debug_marker("compute mandelbrot");
-        cpass.dispatch_workgroups(wg_size.0, wg_size.1, wg_size.2);
+// adapter a session would render on, which the tuned configurations are kept for
+pub fn selected_adapter_info(params: &Parameters) -> Result<wgpu::AdapterInfo, Box<dyn std::error::Error>> {
+    let instance = wgpu::Instance::new(backends(params));
+    Ok(pollster::block_on(select_adapter(&instance, params))?.get_info())
+}
+
+
+// device and pipeline kept alive between renders, only the uniform buffer is written for every render
+pub struct WgpuSession {
+    adapter_info: wgpu::AdapterInfo,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pub baked: crate::backends::session::BakedParameters,
+    compute_pipeline: wgpu::ComputePipeline,
+    uniform_buffer: wgpu::Buffer,
+    // reallocated when the size of the image band changes
+    storage: Option<Storage>,
+    timestamps: Option<Timestamps>,
+    colorize: Option<Colorize>
+}
+
+
+// device local buffer written by the shader and a mappable one its contents are copied to in chunks
+struct Storage {
+    buffer: wgpu::Buffer,
+    staging_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    size: usize,
+    // RGBA pixels written by the colorization pass, with its bind group
+    colors: Option<(wgpu::Buffer, wgpu::BindGroup)>
+}
+
+
+// colorization pass run after the mandelbrot one, when the device applies the colormap
+struct Colorize {
+    pipeline: wgpu::ComputePipeline,
+    lut_buffer: wgpu::Buffer
+}
+
+
+impl Colorize {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let shader_module = crate::shaders::provider::get_colorize_wgpu_shader_module(device);
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: None,
+            module: &shader_module,
+            entry_point: "main",
+        });
+
+        let lut = crate::colormap::inferno_lut();
+        let lut_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: lut.len() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&lut_buffer, 0, &lut);
+
+        Colorize { pipeline, lut_buffer }
     }
+}
 
-    let init_time = start_time.elapsed();
 
-    queue.submit(Some(encoder.finish()));
+// timestamps written around the dispatch and resolved into a mappable buffer
+struct Timestamps {
+    query_set: wgpu::QuerySet,
+    readback_buffer: wgpu::Buffer
+}
+
+
+impl Timestamps {
+    const SIZE: wgpu::BufferAddress = 2 * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+
+    fn new(device: &wgpu::Device) -> Self {
+        Timestamps {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: None,
+                ty: wgpu::QueryType::Timestamp,
+                count: 2,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: Self::SIZE,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            })
+        }
+    }
+
+    // has to be called after the buffer was mapped
+    fn elapsed(&self, queue: &wgpu::Queue) -> std::time::Duration {
+        let view = self.readback_buffer.slice(..).get_mapped_range();
+        let start = u64::from_le_bytes(view[0..8].try_into().unwrap());
+        let end = u64::from_le_bytes(view[8..16].try_into().unwrap());
+        drop(view);
+        self.readback_buffer.unmap();
+        std::time::Duration::from_nanos((end.saturating_sub(start) as f64 * queue.get_timestamp_period() as f64) as u64)
+    }
+}
+
+
+impl WgpuSession {
+    pub async fn new(params: &Parameters) -> Result<Self, Box<dyn std::error::Error>> {
+        // Instantiates instance of WebGPU
+        let instance = wgpu::Instance::new(backends(params));
+        let adapter = select_adapter(&instance, params).await?;
+        Self::with_adapter(&adapter, params).await
+    }
+
+    pub async fn with_adapter(adapter: &wgpu::Adapter, params: &Parameters) -> Result<Self, Box<dyn std::error::Error>> {
+        let (device, queue) = request_device(adapter).await
+            .map_err(|error| format!("Cannot create a device on {}: {error}", adapter.get_info().name))?;
+        let adapter_info = adapter.get_info();
+        tracing::info!("Selected device: {adapter_info:?}");
+
+        let shader_module = crate::shaders::provider::get_wgpu_shader_module(params, &device)?;
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: None,
+            module: &shader_module,
+            entry_point: "main",
+        });
+
+        let uniform_data = crate::shaders::provider::get_uniform_data(params);
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: uniform_data.len().max(32) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let timestamps = device.features().contains(wgpu::Features::TIMESTAMP_QUERY).then(|| Timestamps::new(&device));
+        if timestamps.is_none() {
+            tracing::debug!("Adapter does not support timestamp queries, computation time will be a CPU-side estimate");
+        }
+
+        let colorize = params.gpu_colorize.then(|| Colorize::new(&device, &queue));
+
+        Ok(WgpuSession {
+            adapter_info,
+            device,
+            queue,
+            baked: crate::backends::session::BakedParameters::new(params),
+            compute_pipeline,
+            uniform_buffer,
+            storage: None,
+            timestamps,
+            colorize
+        })
+    }
+
+    fn reserve_storage(&mut self, size: usize) {
+        if self.storage.as_ref().is_none_or(|storage| storage.size != size) {
+            let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: size as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+            // colors take 4 bytes per pixel
+            let readback_size = if self.colorize.is_some() { 4 * size } else { size };
+            let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: readback_size.min(READBACK_CHUNK_SIZE) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+
+            let bind_group_layout = self.compute_pipeline.get_bind_group_layout(0);
+            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: self.uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+            });
+
+            let colors = self.colorize.as_ref().map(|colorize| {
+                let colors_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: None,
+                    size: 4 * size as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                });
+                let colors_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: None,
+                    layout: &colorize.pipeline.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: colorize.lut_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: colors_buffer.as_entire_binding(),
+                        },
+                    ],
+                });
+                (colors_buffer, colors_bind_group)
+            });
+            self.storage = Some(Storage { buffer, staging_buffer, bind_group, size, colors });
+        }
+    }
+
+    // copies `len` bytes of the buffer through the staging buffer, chunk by chunk
+    async fn read_buffer<P: bytemuck::Pod>(&self, buffer: &wgpu::Buffer, len: usize) -> Vec<P> {
+        let storage = self.storage.as_ref().unwrap();
+        let mut data = Vec::with_capacity(len / std::mem::size_of::<P>());
+        for offset in (0..len).step_by(READBACK_CHUNK_SIZE) {
+            // copies are aligned to 4 bytes, the storage buffer is always big enough for the padding
+            let chunk_len = (len - offset).min(READBACK_CHUNK_SIZE)
+                .next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT as usize) as wgpu::BufferAddress;
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            encoder.copy_buffer_to_buffer(buffer, offset as wgpu::BufferAddress, &storage.staging_buffer, 0, chunk_len);
+            self.queue.submit(Some(encoder.finish()));
+
+            let buffer_slice = storage.staging_buffer.slice(..chunk_len);
+            let (sender, receiver) = futures::channel::oneshot::channel::<Result<(), wgpu::BufferAsyncError>>();
+            buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
+
+            self.device.poll(wgpu::Maintain::Wait);
+
+            receiver.await.unwrap().unwrap();
+            let data_view = buffer_slice.get_mapped_range();
+            data.extend_from_slice(bytemuck::cast_slice(&data_view[..data_view.len().min(len - offset)]));
+
+            // With the current interface, we have to make sure all mapped views are
+            // dropped before we unmap the buffer.
+            drop(data_view);
+            storage.staging_buffer.unmap();
+        }
+        data
+    }
+
+    pub async fn render(&mut self, params: &Parameters) -> Result<crate::result::ComputeResult, Box<dyn std::error::Error>> {
+        self.baked.check(params)?;
+        let start_time = std::time::Instant::now();
+
+        let (wg_size, uniform_data) = crate::shaders::provider::get_configuration(params);
+        self.queue.write_buffer(&self.uniform_buffer, 0, &uniform_data);
+
+        let storage_buffer_size: usize = std::mem::size_of::<u8>() * params.img_size_px as usize * crate::shaders::provider::dispatched_rows(params);
+        self.reserve_storage(storage_buffer_size);
+        let storage = self.storage.as_ref().unwrap();
 
-    let computation_time = start_time.elapsed() - init_time;
+        let mut encoder =
+            self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        if let Some(timestamps) = &self.timestamps {
+            encoder.write_timestamp(&timestamps.query_set, 0);
+        }
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            cpass.set_pipeline(&self.compute_pipeline);
+            cpass.set_bind_group(0, &storage.bind_group, &[]);
+            cpass.insert_debug_marker("compute mandelbrot");
+            cpass.dispatch_workgroups(wg_size.0, wg_size.1, wg_size.2);
+        }
+        if let (Some(colorize), Some((_, colors_bind_group))) = (&self.colorize, &storage.colors) {
+            let colorize_size = crate::shaders::provider::get_colorize_dispatch(params);
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            cpass.set_pipeline(&colorize.pipeline);
+            cpass.set_bind_group(0, colors_bind_group, &[]);
+            cpass.insert_debug_marker("colorize");
+            cpass.dispatch_workgroups(colorize_size.0, colorize_size.1, colorize_size.2);
+        }
+        if let Some(timestamps) = &self.timestamps {
+            encoder.write_timestamp(&timestamps.query_set, 1);
+            encoder.resolve_query_set(&timestamps.query_set, 0..2, &timestamps.readback_buffer, 0);
+        }
 
-    let buffer_slice = storage_buffer.slice(..);
-    let (sender, receiver) = futures::channel::oneshot::channel::<Result<(), wgpu::BufferAsyncError>>();
-    buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
+        let init_time = start_time.elapsed();
 
-    device.poll(wgpu::Maintain::Wait);
+        self.queue.submit(Some(encoder.finish()));
 
-    receiver.await.unwrap().unwrap();
-    let data_view = buffer_slice.get_mapped_range();
-    let data = data_view.to_vec();
+        if let Some(timestamps) = &self.timestamps {
+            timestamps.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, |v| v.unwrap());
+        }
 
-    // With the current interface, we have to make sure all mapped views are
-    // dropped before we unmap the buffer.
-    drop(data_view);
-    storage_buffer.unmap();
+        // submit returns immediately, the work is done once the device is polled
+        self.device.poll(wgpu::Maintain::Wait);
+        let wait_time = start_time.elapsed() - init_time;
 
-    crate::result::ComputeResult {
+        // rows of the last workgroup past the requested ones are not read back
+        let pixels = params.img_size_px as usize * params.rows.len();
+        let data = self.read_buffer(&storage.buffer, pixels).await;
+        let colors = match &storage.colors {
+            Some((colors_buffer, _)) => Some(self.read_buffer(colors_buffer, 4 * pixels).await),
+            None => None
+        };
+
+        // without timestamps the whole wait for the device is counted as computation
+        let computation_time = match &self.timestamps {
+            Some(timestamps) => timestamps.elapsed(&self.queue).min(wait_time),
+            None => wait_time
+        };
+
+        Ok(crate::result::ComputeResult {
+            data,
+            colors,
+            costs: None,
+            initialization_time: init_time,
+            computation_time,
+            computation_time_estimated: self.timestamps.is_none(),
+            data_fetch_time: start_time.elapsed() - computation_time - init_time
+        })
+    }
+}
+
+
+// every adapter, software ones included, takes tiles of rows from a shared queue until none remain, adapters which
+// cannot create a device are skipped
+pub fn run_wgpu_all_adapters(params: &Parameters) -> Result<crate::result::ComputeResult, Box<dyn std::error::Error>> {
+    let start_time = std::time::Instant::now();
+    let instance = wgpu::Instance::new(backends(params));
+    let adapters = enumerate_adapters(&instance, params);
+    if adapters.is_empty() {
+        return Err("No adapters found".into());
+    }
+    let remaining = Mutex::new(params.rows.clone());
+    let init_time = start_time.elapsed();
+
+    let workers = std::thread::scope(|scope| {
+        let workers: Vec<_> = adapters.iter().enumerate().map(|(idx, adapter)| {
+            let remaining = &remaining;
+            scope.spawn(move || pollster::block_on(async move {
+                let device_start_time = std::time::Instant::now();
+                let mut session = match WgpuSession::with_adapter(adapter, params).await {
+                    Ok(session) => session,
+                    Err(error) => {
+                        tracing::warn!("Skipping adapter {idx}: {error}");
+                        return None;
+                    }
+                };
+                let device_time = device_start_time.elapsed();
+
+                let mut tiles = Vec::new();
+                loop {
+                    let tile = {
+                        let mut remaining = remaining.lock().unwrap();
+                        let tile = remaining.start..remaining.end.min(remaining.start.saturating_add(MULTI_ADAPTER_TILE_ROWS));
+                        remaining.start = tile.end;
+                        tile
+                    };
+                    if tile.is_empty() {
+                        break;
+                    }
+                    let tile_params = Parameters { rows: tile.clone(), ..params.clone() };
+                    tiles.push((tile, session.render(&tile_params).await.unwrap()));
+                }
+
+                let info = &session.adapter_info;
+                tracing::debug!("Adapter {idx} ({}, {:?}): {} tiles, {} rows\n\
+                                 * device         {device_time:.1?}\n\
+                                 * initialization {:.1?}\n\
+                                 * computation    {:.1?}\n\
+                                 * data fetch     {:.1?}",
+                    info.name, info.device_type, tiles.len(), tiles.iter().map(|(rows, _)| rows.len()).sum::<usize>(),
+                    tiles.iter().map(|(_, r)| r.initialization_time).sum::<std::time::Duration>(),
+                    tiles.iter().map(|(_, r)| r.computation_time).sum::<std::time::Duration>(),
+                    tiles.iter().map(|(_, r)| r.data_fetch_time).sum::<std::time::Duration>()
+                );
+                Some(tiles)
+            }))
+        }).collect();
+        // tiles taken by a worker which panicked are lost
+        workers.into_iter().map(|worker| worker.join().map_err(|_| "Rendering on an adapter failed")).collect::<Result<Vec<_>, _>>()
+    });
+
+    // workers of skipped adapters took no tiles
+    let adapter_tiles: Vec<Vec<_>> = workers?.into_iter().flatten().collect();
+    let tiles: Vec<_> = adapter_tiles.iter().flatten().collect();
+    if tiles.iter().map(|(rows, _)| rows.len()).sum::<usize>() < params.rows.len() {
+        return Err("None of the adapters can create a device".into());
+    }
+    let (data, colors) = crate::result::stitch_bands(params, &tiles);
+
+    // adapters compute in parallel, the busiest one determines the computation time, the rest of the wall time is
+    // spent setting up the devices and fetching the data
+    let computation_time = adapter_tiles.iter()
+        .map(|tiles| tiles.iter().map(|(_, r)| r.computation_time).sum::<std::time::Duration>())
+        .max().unwrap();
+    Ok(crate::result::ComputeResult {
         data,
+        colors,
+        costs: None,
         initialization_time: init_time,
         computation_time,
-        data_fetch_time: start_time.elapsed() - computation_time - init_time
-    }
+        computation_time_estimated: tiles.iter().any(|(_, r)| r.computation_time_estimated),
+        data_fetch_time: start_time.elapsed().saturating_sub(init_time + computation_time)
+    })
 }
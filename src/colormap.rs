@@ -0,0 +1,54 @@
+use std::sync::OnceLock;
+
+
+static INFERNO: OnceLock<Vec<[u8; 3]>> = OnceLock::new();
+
+
+pub fn inferno() -> &'static [[u8; 3]] {
+    INFERNO.get_or_init(|| {
+        scarlet::colormap::ListedColorMap::inferno().vals.iter().map(|[r, g, b]| {
+            [(*r as f32 * 255.0) as u8,
+             (*g as f32 * 255.0) as u8,
+             (*b as f32 * 255.0) as u8]
+        }).collect()
+    })
+}
+
+
+// opaque RGBA8 pixel of the color, the layout of the colors written by the GPU colorization
+pub fn rgba([r, g, b]: [u8; 3]) -> [u8; 4] {
+    [r, g, b, u8::MAX]
+}
+
+
+// colormap as RGBA8 words, uploaded as the lookup table of the GPU colorization
+pub fn inferno_lut() -> Vec<u8> {
+    inferno().iter().flat_map(|color| rgba(*color)).collect()
+}
+
+
+fn srgb_to_linear(v: u8) -> f32 {
+    let v = v as f32 / 255.0;
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+
+fn linear_to_srgb(v: f32) -> u8 {
+    let v = if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 };
+    (v * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+
+// colors of the values averaged in linear RGB, averaging sRGB components directly darkens the edges
+pub fn average_colors(values: impl Iterator<Item = u8>) -> [u8; 4] {
+    let colormap = inferno();
+    let mut sum = [0.0f32; 3];
+    let mut count = 0;
+    for value in values {
+        for (sum, component) in sum.iter_mut().zip(colormap[value as usize]) {
+            *sum += srgb_to_linear(component);
+        }
+        count += 1;
+    }
+    rgba(sum.map(|v| linear_to_srgb(v / count as f32)))
+}
@@ -0,0 +1,120 @@
+use crate::parameters::Parameters;
+
+
+// ratio of the most expensive unit of work to the average one, 1.0 means perfectly balanced units
+fn imbalance(unit_costs: &[u64]) -> f64 {
+    let max = *unit_costs.iter().max().unwrap() as f64;
+    let mean = unit_costs.iter().sum::<u64>() as f64 / unit_costs.len() as f64;
+    if mean == 0.0 { 1.0 } else { max / mean }
+}
+
+
+pub fn log_statistics(params: &Parameters, costs: &[u32]) {
+    let size = params.img_size_px as usize;
+    let tile_size = params.tile_size_px as usize;
+    let tiles_per_side = size.div_ceil(tile_size);
+
+    let total: u64 = costs.iter().map(|c| *c as u64).sum();
+    let mut row_costs = vec![0u64; size];
+    let mut tile_costs = vec![0u64; tiles_per_side * tiles_per_side];
+    for (i, cost) in costs.iter().enumerate() {
+        let (x, y) = (i % size, i / size);
+        row_costs[y] += *cost as u64;
+        tile_costs[(y / tile_size) * tiles_per_side + x / tile_size] += *cost as u64;
+    }
+
+    tracing::debug!("Cost map:\n\
+                     * iterations      {total}\n\
+                     * per pixel       {:.1} mean, {} max\n\
+                     * row imbalance   x{:.2}\n\
+                     * tile imbalance  x{:.2} ({tile_size}x{tile_size}px)",
+        total as f64 / costs.len() as f64, costs.iter().max().unwrap(),
+        imbalance(&row_costs), imbalance(&tile_costs)
+    );
+}
+
+
+// png is written as a heatmap normalized to the most expensive pixel, anything else as raw little-endian u32 values
+pub fn save(params: &Parameters, costs: &[u32], path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    let size = params.img_size_px as u32;
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("png")) {
+        let colormap = crate::colormap::inferno();
+        let max = (*costs.iter().max().unwrap()).max(1) as f64;
+        let heatmap: Vec<u8> = costs.iter()
+            .flat_map(|c| colormap[(*c as f64 / max * 255.0).round() as usize])
+            .collect();
+        image::RgbImage::from_raw(size, size, heatmap).unwrap().save(path)?;
+    } else {
+        let raw: Vec<u8> = costs.iter().flat_map(|c| c.to_le_bytes()).collect();
+        std::fs::write(path, raw)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::backends::cpu::run_cpu_par_iter;
+    use crate::parameters::{parse_params, WorkUnit};
+
+
+    // costs counted by the render, which are the same whatever the order of the work
+    fn counted_costs(args: &[&str]) -> Vec<u32> {
+        let options = ["cpu-par-iter", "16", "100", "--cost-map", "costs.png", "--no-symmetry"];
+        let args = [&options[..], args, &["--", "-2.2", "0.8", "-1.5", "1.5"]].concat();
+        run_cpu_par_iter(&parse_params(&args).unwrap()).costs.unwrap()
+    }
+
+
+    #[test]
+    fn counts_costs_in_every_work_unit() {
+        let costs = counted_costs(&[]);
+        assert_eq!(costs.len(), 16 * 16);
+        // 16 samples of at most 100 iterations
+        assert!(costs.iter().all(|c| *c <= 16 * 100));
+        for work_unit in [WorkUnit::Pixels, WorkUnit::Tiles, WorkUnit::MortonTiles] {
+            let name = clap::ValueEnum::to_possible_value(&work_unit).unwrap().get_name().to_string();
+            assert!(counted_costs(&["--work-unit", &name, "--tile-size", "4"]) == costs);
+        }
+        // samples of a pixel add up, shading them does not change what they cost
+        let single = counted_costs(&["--samples", "1"]);
+        assert!(single.iter().zip(&costs).all(|(s, c)| s <= c));
+        assert!(counted_costs(&["--color-average"]) == costs);
+    }
+
+
+    #[test]
+    fn refuses_renders_which_do_not_count_costs() {
+        let view = ["--", "-2.2", "0.8", "-1.5", "1.5"];
+        let parse = |args: &[&str]| parse_params(&[args, &view[..]].concat()).map(|_| ());
+        assert!(parse(&["cpu-mariani-silver", "64", "100", "--cost-map", "costs.png", "--no-symmetry"]).is_err());
+        assert!(parse(&["opengl-glsl", "64", "100", "--cost-map", "costs.png", "--no-symmetry"]).is_err());
+        assert!(parse(&["cpu-par-iter", "64", "100", "--cost-map", "costs.png"]).is_err());
+        assert!(parse(&["cpu-par-iter", "64", "100", "--cost-map", "costs.png", "--no-symmetry", "--samples", "4", "--adaptive-sampling"]).is_err());
+        assert!(parse(&["cpu-par-iter", "64", "100", "--cost-map", "costs.png", "--no-symmetry"]).is_ok());
+    }
+
+
+    #[test]
+    fn saves_raw_costs() {
+        let params = parse_params(&["cpu-par-iter", "16", "100", "--", "-2.2", "0.8", "-1.5", "1.5"]).unwrap();
+        let costs = counted_costs(&[]);
+
+        let path = std::env::temp_dir().join(format!("mandelbrot-cost-map-{}.raw", std::process::id()));
+        super::save(&params, &costs, &path).unwrap();
+        let raw = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let saved: Vec<u32> = raw.chunks(4).map(|c| u32::from_le_bytes(c.try_into().unwrap())).collect();
+        assert!(saved == costs);
+    }
+
+
+    #[test]
+    fn reports_unwritable_path() {
+        let params = parse_params(&["cpu-par-iter", "16", "100", "--", "-2.2", "0.8", "-1.5", "1.5"]).unwrap();
+        let costs = counted_costs(&[]);
+        let directory = std::env::temp_dir().join("mandelbrot-missing-directory");
+        assert!(super::save(&params, &costs, &directory.join("costs.png")).is_err());
+        assert!(super::save(&params, &costs, &directory.join("costs.raw")).is_err());
+    }
+}
@@ -1,12 +1,24 @@
+
+mod auto_iter;
+mod autotune;
 mod backends;
+mod colormap;
+mod cost_map;
+// generated by gl_generator in build.rs
+#[allow(clippy::missing_transmute_annotations, clippy::unused_unit, clippy::too_many_arguments, clippy::upper_case_acronyms)]
 mod gl {
     include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 }
 mod parameters;
+mod precision;
+mod refine;
 mod result;
+mod sampling;
 mod shaders;
+mod symmetry;
 
-use crate::parameters::BackendType;
+use crate::backends::session::GpuSession;
+use crate::parameters::{BackendType, WorkUnit};
 use crate::result::ComputeResult;
 
 
@@ -21,57 +33,153 @@ pub fn init_logger(log_level: tracing::Level, log_spans: tracing_subscriber::fmt
 }
 
 
-fn process_col(img_size: u32, data: &[u8]) -> image::RgbImage {
-    let colormap = scarlet::colormap::ListedColorMap::inferno().vals;
-    let colormap: Vec<[u8; 3]> = colormap.iter().map(|[r, g, b]| {
-        [(*r as f32 * 255.0) as u8,
-         (*g as f32 * 255.0) as u8,
-         (*b as f32 * 255.0) as u8]
-    }).collect();
-
-    let color_image_data: Vec<u8> = data.iter().flat_map(|v| { colormap[*v as usize] }).collect();
-    image::RgbImage::from_raw(img_size, img_size, color_image_data).unwrap()
+// colors computed with the result are encoded as they are, RGBA
+fn process_col(img_size: u32, result: &ComputeResult) -> image::DynamicImage {
+    match &result.colors {
+        Some(colors) => image::RgbaImage::from_raw(img_size, img_size, colors.iter().flatten().copied().collect()).unwrap().into(),
+        None => {
+            let colormap = colormap::inferno();
+            let color_image_data: Vec<u8> = result.data.iter().flat_map(|v| { colormap[*v as usize] }).collect();
+            image::RgbImage::from_raw(img_size, img_size, color_image_data).unwrap().into()
+        }
+    }
 }
 
 
-fn get_result_from_backend(params: &parameters::Parameters) -> ComputeResult {
-    match params.backend_type {
+// GPU backends render with the session kept by the caller, which is set up again only when the pipelines change
+fn get_result_from_backend(
+    params: &parameters::Parameters,
+    session: &mut Option<GpuSession>
+) -> Result<ComputeResult, Box<dyn std::error::Error>> {
+    Ok(match params.backend_type {
         BackendType::CpuLoop =>
-            backends::cpu::run_cpu_loops(&params),
+            backends::cpu::run_cpu_loops(params),
         BackendType::CpuIter =>
-            backends::cpu::run_cpu_iter(&params),
+            backends::cpu::run_cpu_iter(params),
         BackendType::CpuParIter =>
-            backends::cpu::run_cpu_par_iter(&params),
-        BackendType::OpenglSpirv | BackendType::OpenglSpirvU8 =>
-            unsafe { backends::opengl::run_opengl(&params) },
+            backends::cpu::run_cpu_par_iter(params),
+        BackendType::CpuSimd =>
+            backends::simd::run_cpu_simd(params),
+        BackendType::CpuMarianiSilver =>
+            backends::mariani_silver::run_cpu_mariani_silver(params),
+        BackendType::WgpuSpirv | BackendType::WgpuWgsl if params.all_adapters =>
+            backends::wgpu::run_wgpu_all_adapters(params)?,
+        BackendType::OpenglSpirv | BackendType::OpenglSpirvU8 | BackendType::OpenglGlsl | BackendType::OpenglFragment |
         BackendType::WgpuSpirv | BackendType::WgpuWgsl =>
-            pollster::block_on(backends::wgpu::run_wgpu(&params))
-    }
+            backends::session::render_reusing(session, params)?,
+        BackendType::Hybrid =>
+            backends::hybrid::run_hybrid(params, session)?
+    })
 }
 
 
-fn run(params: &parameters::Parameters, store_to_file: bool) {
+fn run(
+    params: &parameters::Parameters,
+    session: &mut Option<GpuSession>,
+    store_to_file: bool
+) -> Result<(), Box<dyn std::error::Error>> {
     let start_time = std::time::Instant::now();
-    let result = get_result_from_backend(&params);
+    let mut result = symmetry::render(params, |params| {
+        sampling::render(params, |params| get_result_from_backend(params, session))
+    })?;
     tracing::info!("Computing time: {:.1?}", start_time.elapsed());
+
     tracing::debug!("In computation time:\n\
                      * initialization {:.1?}\n\
-                     * computation    {:.1?}\n\
+                     * computation    {:.1?}{}\n\
                      * data fetch     {:.1?}",
-        result.initialization_time, result.computation_time, result.data_fetch_time
+        result.initialization_time, result.computation_time,
+        if result.computation_time_estimated { " (CPU-side estimate, device timing unavailable)" } else { "" },
+        result.data_fetch_time
     );
+    if let Some(path) = &params.cost_map {
+        // parameters accept the cost map only with the backends counting it
+        let costs = result.costs.as_ref().unwrap();
+        cost_map::log_statistics(params, costs);
+        if let Err(error) = cost_map::save(params, costs, path) {
+            tracing::error!("Cannot write the cost map to {}: {error}", path.display());
+        }
+    }
+    if params.backend_type == BackendType::CpuParIter {
+        let threads = params.thread_pool.as_ref().map_or_else(rayon::current_num_threads, |pool| pool.current_num_threads());
+        match params.work_unit {
+            WorkUnit::Tiles | WorkUnit::MortonTiles =>
+                tracing::debug!("Work units: {:?} of {}x{}px on {threads} threads", params.work_unit, params.tile_size_px, params.tile_size_px),
+            _ => tracing::debug!("Work units: {:?} on {threads} threads", params.work_unit)
+        }
+    }
+
+    if params.benchmark && params.backend_type != BackendType::CpuParIter {
+        let reference_params = parameters::Parameters { backend_type: BackendType::CpuParIter, ..params.clone() };
+        let reference = sampling::render(&reference_params, |params| get_result_from_backend(params, &mut None))?;
+        let reference_time = reference.initialization_time + reference.computation_time + reference.data_fetch_time;
+        let time = result.initialization_time + result.computation_time + result.data_fetch_time;
+        tracing::info!("Speed-up over cpu-par-iter: x{:.2} ({reference_time:.1?} vs {time:.1?})",
+            reference_time.as_secs_f64() / time.as_secs_f64());
+        if reference.data != result.data {
+            let differing = reference.data.iter().zip(&result.data).filter(|(a, b)| a != b).count();
+            tracing::warn!("{differing} pixels differ from cpu-par-iter");
+        }
+    }
+
+    // benchmark compares unrefined results
+    if params.refine_threshold > 0 {
+        let start_time = std::time::Instant::now();
+        refine::refine(params, &mut result, |params| {
+            sampling::render(params, |params| get_result_from_backend(params, session))
+        })?;
+        tracing::info!("Refinement time: {:.1?}", start_time.elapsed());
+    }
 
     if !store_to_file
-    { return; }
+    { return Ok(()); }
 
     let start_time = std::time::Instant::now();
     let img_size = params.img_size_px as u32;
-    let imgbuf = process_col(img_size, result.data.as_slice());
+    let imgbuf = process_col(img_size, &result);
     tracing::info!("Processing time: {:.1?}", start_time.elapsed());
 
     let start_time = std::time::Instant::now();
     imgbuf.save("fractal.png").unwrap();
     tracing::info!("Saving time: {:.1?}", start_time.elapsed());
+    Ok(())
+}
+
+
+// polls the modification time of the --shader file, renders again with the reloaded shader whenever it changes
+fn watch_shader(params: &mut parameters::Parameters, session: &mut Option<GpuSession>) {
+    let path = params.shader_file.clone().unwrap();
+    let modified = || std::fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+    let mut last_modified = modified();
+    tracing::info!("Watching {} for changes", path.display());
+
+    loop {
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        let current = modified();
+        if current == last_modified
+        { continue; }
+        last_modified = current;
+
+        match shaders::loader::load(&path, params.shader_backend()) {
+            Ok(shader) => {
+                tracing::info!("Reloaded {}", path.display());
+                params.shader = Some(std::sync::Arc::new(shader));
+                // the driver may still reject a shader which passed validation, keep watching then with a new session
+                match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| run(params, session, true))) {
+                    Ok(Ok(())) => (),
+                    Ok(Err(error)) => {
+                        tracing::error!("Rendering with {} failed: {error}", path.display());
+                        *session = None;
+                    }
+                    Err(_) => {
+                        tracing::error!("Rendering with {} failed", path.display());
+                        *session = None;
+                    }
+                }
+            }
+            Err(error) => tracing::error!("{error}")
+        }
+    }
 }
 
 
@@ -80,6 +188,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 tracing_subscriber::fmt::format::FmtSpan::ENTER |
                     tracing_subscriber::fmt::format::FmtSpan::CLOSE
     )?;
-    let params = parameters::get_params();
-    Ok(run(&params, true))
+    let mut params = parameters::get_params()?;
+    // one GPU session for the first render and every one with a reloaded shader
+    let mut session = None;
+    run(&params, &mut session, true)?;
+    if params.watch {
+        watch_shader(&mut params, &mut session);
+    }
+    Ok(())
 }
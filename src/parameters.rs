@@ -1,23 +1,223 @@
 use clap::Parser;
 
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+// keeps sum of all supersamples of a pixel within u32
+pub const MAX_ITER_LIMIT: u32 = 1 << 24;
+
+// invocations of a workgroup every wgpu adapter and OpenGL 4.3 context supports
+const MAX_WORKGROUP_INVOCATIONS: u32 = 256;
+const DEFAULT_WORKGROUP_SIZE: [u16; 2] = [16, 16];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
 pub enum BackendType {
     CpuLoop,
     CpuIter,
     CpuParIter,
+    CpuSimd,
+    CpuMarianiSilver,
     OpenglSpirv,
     OpenglSpirvU8,
+    OpenglGlsl,
+    OpenglFragment,
     WgpuSpirv,
-    WgpuWgsl
+    WgpuWgsl,
+    Hybrid
+}
+
+
+impl BackendType {
+    pub fn is_gpu(&self) -> bool {
+        matches!(self, BackendType::OpenglSpirv | BackendType::OpenglSpirvU8 | BackendType::OpenglGlsl |
+            BackendType::OpenglFragment | BackendType::WgpuSpirv | BackendType::WgpuWgsl)
+    }
+
+    pub fn supports_precision(&self, precision: Precision) -> bool {
+        match self {
+            BackendType::CpuLoop | BackendType::CpuIter | BackendType::CpuParIter | BackendType::CpuMarianiSilver => true,
+            _ => precision == Precision::Single
+        }
+    }
+
+    pub fn supports_color_average(&self) -> bool {
+        *self == BackendType::CpuParIter
+    }
+
+    pub fn supports_gpu_colorize(&self) -> bool {
+        // colorization is a compute pass
+        self.is_gpu() && *self != BackendType::OpenglFragment
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum Precision {
+    Single,
+    Double
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum WorkUnit {
+    Pixels,
+    Rows,
+    Tiles,
+    MortonTiles
+}
+
+
+// EGL platform the OpenGL contexts are created on, neither needs a display
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum GlPlatform {
+    Surfaceless,
+    Device
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum WgpuBackend {
+    Vulkan,
+    Gl,
+    Any
+}
+
+
+#[derive(Clone, Debug)]
+pub enum AdapterSelector {
+    Index(usize),
+    // case insensitive substring of the adapter name
+    Name(String)
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum SamplePattern {
+    Grid,
+    Jittered,
+    RotatedGrid
+}
+
+
+#[derive(Copy, Clone, Default)]
+pub struct InteriorChecks {
+    // main cardioid and period-2 bulb
+    pub bulbs: bool,
+    pub periodicity: bool
+}
+
+
+#[derive(Clone)]
+pub struct Sampling {
+    // offsets of the samples from the pixel coordinates, in pixels
+    pub offsets: Vec<[f32; 2]>,
+    // only pixels differing from their neighbours are supersampled
+    pub adaptive: bool,
+    // samples are averaged as colors in linear RGB instead of as iteration counts
+    pub color_average: bool
 }
 
 
+#[derive(Clone)]
 pub struct Parameters {
     pub backend_type: BackendType,
+    // renders the bands not taken by cpu-par-iter in the hybrid backend
+    pub hybrid_gpu_backend: BackendType,
     pub img_size_px: u16,
-    pub max_iter: u8,
-    pub limits: [f32; 4]
+    // number of iterations mapped to the brightest value, and run unless a budget is given
+    pub max_iter: u32,
+    // iterations run per sample by the refinement passes, beyond max_iter
+    pub iteration_budget: Option<u32>,
+    pub limits: [f64; 4],
+    pub precision: Precision,
+    pub interior_checks: InteriorChecks,
+    pub sampling: Sampling,
+    // rows of the image to compute, backends return only these
+    pub rows: std::ops::Range<u16>,
+    pub symmetry: bool,
+    pub work_unit: WorkUnit,
+    pub tile_size_px: u16,
+    // built once per run from --threads, the global rayon pool is used when none
+    pub thread_pool: Option<std::sync::Arc<rayon::ThreadPool>>,
+    // compute shader workgroups, width and height in invocations
+    pub workgroup_size: [u16; 2],
+    // consecutive pixels of a row computed by one invocation
+    pub pixels_per_invocation: u16,
+    pub gl_platform: GlPlatform,
+    pub wgpu_backend: WgpuBackend,
+    pub adapter: Option<AdapterSelector>,
+    pub force_fallback_adapter: bool,
+    pub all_adapters: bool,
+    // refinement pass stops when fewer pixels change, refinement is off when 0
+    pub refine_threshold: usize,
+    pub cost_map: Option<std::path::PathBuf>,
+    // colormap is applied by the device, which returns RGBA pixels next to the values
+    pub gpu_colorize: bool,
+    // replaces the built-in mandelbrot shader of the GPU backend, loaded from shader_file
+    pub shader: Option<std::sync::Arc<crate::shaders::loader::Shader>>,
+    pub shader_file: Option<std::path::PathBuf>,
+    pub watch: bool,
+    pub benchmark: bool
+}
+
+
+impl Parameters {
+    pub fn budget(&self) -> u32 {
+        self.iteration_budget.unwrap_or(self.max_iter)
+    }
+
+    // value 0 is left for the pixels none of whose samples escaped, refinement takes them for saturated
+    pub fn marks_saturated(&self) -> bool {
+        self.refine_threshold > 0
+    }
+
+    // limits of the view shifted so that its first row is the first computed row
+    pub fn band_limits(&self) -> [f64; 4] {
+        let [xmin, xmax, ymin, ymax] = self.limits;
+        let band_ymin = ymin + (ymax - ymin) * self.rows.start as f64 / self.img_size_px as f64;
+        [xmin, xmax, band_ymin, band_ymin + (ymax - ymin)]
+    }
+
+    // backend running the shaders, the GPU one in the hybrid backend
+    pub fn shader_backend(&self) -> BackendType {
+        match self.backend_type {
+            BackendType::Hybrid => self.hybrid_gpu_backend,
+            backend_type => backend_type
+        }
+    }
+}
+
+
+fn parse_sample_count(s: &str) -> Result<u16, String> {
+    match s.parse::<u16>() {
+        Ok(count @ (1 | 4 | 16 | 64)) => Ok(count),
+        _ => Err("possible values: 1, 4, 16, 64".to_string())
+    }
+}
+
+
+fn parse_workgroup_size(s: &str) -> Result<[u16; 2], String> {
+    let size = s.split_once('x')
+        .and_then(|(width, height)| Some([width.parse::<u16>().ok()?, height.parse::<u16>().ok()?]));
+    match size {
+        Some([width, height]) if width > 0 && height > 0 => Ok([width, height]),
+        _ => Err("expected WIDTHxHEIGHT, e.g. 16x16".to_string())
+    }
+}
+
+
+// only the GPU backends are listed as possible values
+fn gpu_backend_parser() -> impl clap::builder::TypedValueParser<Value = BackendType> {
+    use clap::{builder::TypedValueParser, ValueEnum};
+    let gpu_backends = BackendType::value_variants().iter().filter(|b| b.is_gpu());
+    clap::builder::PossibleValuesParser::new(gpu_backends.map(|b| b.to_possible_value().unwrap()))
+        .map(|s| BackendType::from_str(&s, false).unwrap())
+}
+
+
+fn parse_adapter(s: &str) -> Result<AdapterSelector, String> {
+    Ok(match s.parse::<usize>() {
+        Ok(index) => AdapterSelector::Index(index),
+        Err(_) => AdapterSelector::Name(s.to_lowercase())
+    })
 }
 
 
@@ -29,27 +229,333 @@ pub struct Arguments {
     /// Final image size
     pub img_size_px: u16,
 
-    /// Maximal number of iterations for pixel
-    pub max_iter: u8,
+    /// Maximal number of iterations for pixel (upper bound of the budget with --auto-iter)
+    #[arg(value_parser = clap::value_parser!(u32).range(1..=MAX_ITER_LIMIT as i64))]
+    pub max_iter: u32,
+
+
+    pub xmin: f64,
+    pub xmax: f64,
+    pub ymin: f64,
+    pub ymax: f64,
+
+    /// Backend rendering bands together with cpu-par-iter in the hybrid backend
+    #[arg(long, default_value = "wgpu-wgsl", value_parser = gpu_backend_parser())]
+    pub hybrid_gpu: BackendType,
+
+    /// Floating point precision of the calculation, selected from the view when omitted
+    #[arg(long, value_enum)]
+    pub precision: Option<Precision>,
 
+    /// Render even if the precision cannot resolve the view
+    #[arg(long)]
+    pub allow_precision_loss: bool,
 
-    pub xmin: f32,
-    pub xmax: f32,
-    pub ymin: f32,
-    pub ymax: f32
+    /// Switch to the cheapest backend able to resolve the view
+    #[arg(long)]
+    pub auto_backend: bool,
+
+    /// Estimate the iteration budget from the zoom level and the image content
+    #[arg(long)]
+    pub auto_iter: bool,
+
+    /// Skip points inside the main cardioid and the period-2 bulb
+    #[arg(long)]
+    pub bulb_check: bool,
+
+    /// Stop iterating points whose orbit became periodic
+    #[arg(long)]
+    pub periodicity_check: bool,
+
+    /// Number of samples per pixel
+    #[arg(long, default_value_t = 16, value_parser = parse_sample_count)]
+    pub samples: u16,
+
+    /// Placement of the samples within a pixel
+    #[arg(long, value_enum, default_value_t = SamplePattern::Grid)]
+    pub sample_pattern: SamplePattern,
+
+    /// Supersample only the pixels which differ from their neighbours
+    #[arg(long)]
+    pub adaptive_sampling: bool,
+
+    /// Average the colors of the samples in linear RGB instead of their iteration counts
+    #[arg(long)]
+    pub color_average: bool,
+
+    /// Compute every row even if the view is symmetric about the real axis
+    #[arg(long)]
+    pub no_symmetry: bool,
+
+    /// Unit of work scheduled on the rayon threads
+    #[arg(long, value_enum, default_value_t = WorkUnit::Rows)]
+    pub work_unit: WorkUnit,
+
+    /// Side of a square tile for the tiles work units
+    #[arg(long, default_value_t = 32, value_parser = clap::value_parser!(u16).range(1..))]
+    pub tile_size: u16,
+
+    /// Number of threads of the dedicated rayon thread pool (global pool when omitted)
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub threads: Option<u16>,
+
+    /// Workgroup size of the compute shaders as WIDTHxHEIGHT (OpenGL and wgpu backends) [default: tuned or 16x16]
+    #[arg(long, value_parser = parse_workgroup_size)]
+    pub workgroup_size: Option<[u16; 2]>,
+
+    /// Pixels of a row computed by one shader invocation, a multiple of 4 except for opengl-spirv-u8 [default: tuned, 1 for opengl-spirv-u8, 4 otherwise]
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub pixels_per_invocation: Option<u16>,
+
+    /// Benchmark workgroup sizes and pixels per invocation on the adapter and keep the fastest for it (wgpu backends)
+    #[arg(long)]
+    pub autotune: bool,
+
+    /// EGL platform the OpenGL contexts are created on (device: first EGL device)
+    #[arg(long, value_enum, default_value_t = GlPlatform::Surfaceless)]
+    pub gl_platform: GlPlatform,
+
+    /// Graphics API used by the wgpu backends
+    #[arg(long, value_enum, default_value_t = WgpuBackend::Vulkan)]
+    pub wgpu_backend: WgpuBackend,
+
+    /// Adapter used by the wgpu backends, by index or by a part of its name
+    #[arg(long, value_parser = parse_adapter)]
+    pub adapter: Option<AdapterSelector>,
+
+    /// Use only the software fallback adapter in the wgpu backends
+    #[arg(long)]
+    pub force_fallback_adapter: bool,
+
+    /// Share tiles of rows between all the adapters found (wgpu backends)
+    #[arg(long)]
+    pub all_adapters: bool,
+
+    /// Re-iterate saturated pixels next to escaping ones with growing budget
+    #[arg(long)]
+    pub refine: bool,
+
+    /// Refinement stops when fewer pixels than that change in a pass
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u32).range(1..))]
+    pub refine_threshold: u32,
+
+    /// Write iterations run per pixel to the file, as a heatmap for .png, raw little-endian u32 array otherwise
+    /// (cpu-par-iter without adaptive sampling and symmetry)
+    #[arg(long)]
+    pub cost_map: Option<std::path::PathBuf>,
+
+    /// Apply the colormap on the device and read back RGBA pixels (OpenGL and wgpu backends)
+    #[arg(long)]
+    pub gpu_colorize: bool,
+
+    /// Shader replacing the built-in one, WGSL or SPIR-V by the extension (OpenGL and wgpu backends)
+    #[arg(long, value_name = "PATH")]
+    pub shader: Option<std::path::PathBuf>,
+
+    /// Reload the --shader file and render again whenever it changes
+    #[arg(long, requires = "shader")]
+    pub watch: bool,
+
+    /// Also render with cpu-par-iter and report the speed-up against it
+    #[arg(long)]
+    pub benchmark: bool
+}
+
+pub fn get_params() -> Result<Parameters, Box<dyn std::error::Error>> {
+    params_from_arguments(Arguments::parse())
 }
 
-pub fn get_params() -> Parameters {
-    let args = Arguments::parse();
-    Parameters {
+
+// command line as the program would get it, without the program name
+#[cfg(test)]
+pub fn parse_params(args: &[&str]) -> Result<Parameters, Box<dyn std::error::Error>> {
+    params_from_arguments(Arguments::try_parse_from(std::iter::once("mandelbrot").chain(args.iter().copied()))?)
+}
+
+
+fn params_from_arguments(args: Arguments) -> Result<Parameters, Box<dyn std::error::Error>> {
+    let mut params = Parameters {
         backend_type: args.backend_type,
+        hybrid_gpu_backend: args.hybrid_gpu,
         img_size_px: args.img_size_px,
         max_iter: args.max_iter,
+        iteration_budget: None,
         limits: [
             args.xmin,
             args.xmax,
             args.ymin,
             args.ymax
-        ]
+        ],
+        precision: args.precision.unwrap_or(Precision::Single),
+        interior_checks: InteriorChecks {
+            bulbs: args.bulb_check,
+            periodicity: args.periodicity_check
+        },
+        sampling: Sampling {
+            offsets: crate::sampling::sample_offsets(args.samples, args.sample_pattern),
+            adaptive: args.adaptive_sampling,
+            color_average: args.color_average
+        },
+        rows: 0..args.img_size_px,
+        symmetry: !args.no_symmetry,
+        work_unit: args.work_unit,
+        tile_size_px: args.tile_size,
+        thread_pool: match args.threads {
+            Some(threads) => Some(std::sync::Arc::new(
+                rayon::ThreadPoolBuilder::new().num_threads(threads as usize).build()?
+            )),
+            None => None
+        },
+        workgroup_size: args.workgroup_size.unwrap_or(DEFAULT_WORKGROUP_SIZE),
+        pixels_per_invocation: 0,
+        gl_platform: args.gl_platform,
+        wgpu_backend: args.wgpu_backend,
+        adapter: args.adapter,
+        force_fallback_adapter: args.force_fallback_adapter,
+        all_adapters: args.all_adapters,
+        refine_threshold: if args.refine { args.refine_threshold as usize } else { 0 },
+        cost_map: args.cost_map,
+        gpu_colorize: args.gpu_colorize,
+        shader: None,
+        shader_file: args.shader,
+        watch: args.watch,
+        benchmark: args.benchmark
+    };
+
+    crate::precision::select_precision(&mut params, &crate::precision::PrecisionPolicy {
+        automatic: args.precision.is_none(),
+        allow_precision_loss: args.allow_precision_loss,
+        auto_backend: args.auto_backend
+    })?;
+
+    // hybrid backend can do only what the backend sharing the work with cpu-par-iter can
+    let capabilities = params.shader_backend();
+
+    if args.color_average && !params.backend_type.supports_color_average() {
+        tracing::warn!("Backend {:?} does not support averaging colors, iteration counts will be averaged", params.backend_type);
+        params.sampling.color_average = false;
+    }
+    // bands rendered by the CPU in the hybrid backend would still be colorized on the CPU
+    if args.gpu_colorize && !params.backend_type.supports_gpu_colorize() {
+        tracing::warn!("Backend {:?} does not colorize on the GPU, colormap will be applied on the CPU", params.backend_type);
+        params.gpu_colorize = false;
+    }
+
+    // cost map is what the render counted, only cpu-par-iter counts the iterations of every pixel it computes
+    if params.cost_map.is_some() {
+        if params.backend_type != BackendType::CpuParIter {
+            return Err(format!("Backend {:?} does not count iterations per pixel, the cost map needs cpu-par-iter", params.backend_type).into());
+        }
+        if params.sampling.adaptive && params.sampling.offsets.len() > 1 {
+            return Err("Cost map does not count the pixels supersampled by adaptive sampling".into());
+        }
+        if params.symmetry && crate::symmetry::find_mirror(&params).is_some() {
+            return Err("Cost map does not count the mirrored rows of a symmetric view, add --no-symmetry".into());
+        }
+    }
+
+    if args.all_adapters && !matches!(capabilities, BackendType::WgpuSpirv | BackendType::WgpuWgsl) {
+        tracing::warn!("Backend {capabilities:?} does not render on multiple adapters, --all-adapters will be ignored");
+    }
+
+    if let Some(path) = &params.shader_file {
+        // diagnostics span several lines, they are logged rather than debug-printed as the error
+        let shader = crate::shaders::loader::load(path, capabilities).map_err(|error| {
+            tracing::error!("{error}");
+            format!("Cannot load the shader {}", path.display())
+        })?;
+        params.shader = Some(std::sync::Arc::new(shader));
+    }
+
+    let tunable = matches!(capabilities, BackendType::WgpuSpirv | BackendType::WgpuWgsl) && !params.all_adapters;
+    // a typo in the adapter is reported before anything is rendered
+    if tunable && params.adapter.is_some() {
+        crate::backends::wgpu::selected_adapter_info(&params)?;
+    }
+    if args.autotune && !tunable {
+        return Err("Autotuning needs a wgpu backend rendering on a single adapter".into());
+    }
+    // configuration tuned for the adapter applies unless the shape of the work is set explicitly
+    let mut pixels_per_invocation = args.pixels_per_invocation;
+    if tunable && !args.autotune && args.workgroup_size.is_none() && pixels_per_invocation.is_none() {
+        if let Some((workgroup_size, pixels)) = crate::autotune::lookup(&params)? {
+            if (params.img_size_px as u32).is_multiple_of(workgroup_size[0] as u32 * pixels as u32) {
+                tracing::debug!("Using tuned workgroup {}x{}, {pixels} pixels per invocation", workgroup_size[0], workgroup_size[1]);
+                params.workgroup_size = workgroup_size;
+                pixels_per_invocation = Some(pixels);
+            } else {
+                tracing::debug!("Tuned configuration does not fit the image size, using the default one");
+            }
+        }
+    }
+
+    if capabilities.is_gpu() {
+        let [workgroup_width, workgroup_height] = params.workgroup_size;
+        if workgroup_width as u32 * workgroup_height as u32 > MAX_WORKGROUP_INVOCATIONS {
+            return Err(format!("Workgroups can have at most {MAX_WORKGROUP_INVOCATIONS} invocations").into());
+        }
+
+        // words of u32 values pack 4 pixels, the Int8 shader writes single bytes, fragments of the RGBA8 target hold 4
+        // pixels
+        let default_pixels = if capabilities == BackendType::OpenglSpirvU8 { 1 } else { 4 };
+        params.pixels_per_invocation = pixels_per_invocation.unwrap_or(default_pixels);
+        if capabilities == BackendType::OpenglFragment && params.pixels_per_invocation != default_pixels {
+            return Err(format!("Shader of backend {capabilities:?} computes {default_pixels} pixels per invocation").into());
+        }
+        let packed_words = matches!(capabilities, BackendType::WgpuWgsl | BackendType::OpenglGlsl) ||
+            (params.shader.is_none() && matches!(capabilities, BackendType::OpenglSpirv | BackendType::WgpuSpirv));
+        if packed_words && !params.pixels_per_invocation.is_multiple_of(4) {
+            return Err(format!("Backend {capabilities:?} computes pixels per invocation in multiples of 4").into());
+        }
+
+        // rows are divided into full workgroups, the fragment backend has none and only packs 4 pixels into a texel
+        let workgroup_pixels = workgroup_width as u32 * params.pixels_per_invocation as u32;
+        if capabilities == BackendType::OpenglFragment {
+            if !params.img_size_px.is_multiple_of(4) {
+                return Err(format!("Image size must be a multiple of 4 pixels for backend {capabilities:?}").into());
+            }
+        } else if !(params.img_size_px as u32).is_multiple_of(workgroup_pixels) {
+            return Err(format!("Image size must be a multiple of {workgroup_pixels} pixels, the row of a workgroup").into());
+        }
+        // colorization pass has its own workgroups of 16 invocations colorizing 4 pixels each
+        if params.gpu_colorize && !params.img_size_px.is_multiple_of(64) {
+            return Err("Image size must be a multiple of 64 pixels to colorize on the GPU".into());
+        }
+    }
+
+    if args.auto_iter {
+        params.max_iter = crate::auto_iter::select_max_iter(&params, params.max_iter);
+    }
+
+    // tuned for the view as it is going to be rendered
+    if args.autotune {
+        crate::autotune::tune(&mut params)?;
+    }
+
+    Ok(params)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_params, BackendType};
+
+
+    #[test]
+    fn hybrid_shares_work_only_with_gpu_backends() {
+        let args = |gpu| ["hybrid", "64", "100", "--hybrid-gpu", gpu, "--", "-2.2", "0.8", "-1.5", "1.5"];
+        assert_eq!(parse_params(&args("opengl-glsl")).unwrap().shader_backend(), BackendType::OpenglGlsl);
+        assert!(parse_params(&args("cpu-par-iter")).is_err());
+        assert!(parse_params(&args("hybrid")).is_err());
+    }
+
+
+    // fragments pack 4 pixels, there are no workgroups the rows have to be divided into
+    #[test]
+    fn fragment_backend_needs_only_whole_texels() {
+        let args = |size| ["opengl-fragment", size, "100", "--", "-2.2", "0.8", "-1.5", "1.5"];
+        assert!(parse_params(&args("36")).is_ok());
+        assert!(parse_params(&args("34")).is_err());
+        assert!(parse_params(&["opengl-glsl", "36", "100", "--", "-2.2", "0.8", "-1.5", "1.5"]).is_err());
     }
 }
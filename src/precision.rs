@@ -0,0 +1,201 @@
+use crate::parameters::{BackendType, Parameters, Precision};
+
+
+// number of distinct float values required between neighbouring pixels, so that supersamples
+// taken inside the pixel still land on different coordinates
+const MIN_STEPS_PER_PIXEL: f64 = 8.0;
+
+// backends ordered from the fastest to the slowest one, hybrid backend is not faster than its GPU backend alone
+// when the GPU is much faster than the CPU, which it usually is
+const BACKENDS_BY_COST: [BackendType; 12] = [
+    BackendType::WgpuWgsl,
+    BackendType::WgpuSpirv,
+    BackendType::OpenglGlsl,
+    BackendType::OpenglSpirv,
+    BackendType::OpenglSpirvU8,
+    BackendType::OpenglFragment,
+    BackendType::Hybrid,
+    BackendType::CpuSimd,
+    BackendType::CpuMarianiSilver,
+    BackendType::CpuParIter,
+    BackendType::CpuIter,
+    BackendType::CpuLoop
+];
+
+const PRECISIONS_BY_COST: [Precision; 2] = [Precision::Single, Precision::Double];
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Resolution {
+    // neighbouring pixels collapse onto the same coordinates
+    Lost,
+    // pixels are distinct, but supersamples inside them are not
+    Degraded,
+    Full
+}
+
+
+pub struct PrecisionPolicy {
+    pub automatic: bool,
+    pub allow_precision_loss: bool,
+    pub auto_backend: bool
+}
+
+
+pub struct PrecisionAnalysis {
+    pub pixel_spacing: f64,
+    pub magnitude: f64
+}
+
+
+impl PrecisionAnalysis {
+    pub fn new(params: &Parameters) -> Self {
+        let img_size = params.img_size_px as f64;
+        let [xmin, xmax, ymin, ymax] = params.limits;
+        PrecisionAnalysis {
+            pixel_spacing: ((xmax - xmin).abs() / img_size).min((ymax - ymin).abs() / img_size),
+            magnitude: params.limits.iter().fold(0.0, |acc, v| acc.max(v.abs()))
+        }
+    }
+
+    // distance between two consecutive floats at the magnitude of the view
+    pub fn ulp(&self, precision: Precision) -> f64 {
+        let epsilon = match precision {
+            Precision::Single => f32::EPSILON as f64,
+            Precision::Double => f64::EPSILON
+        };
+        2f64.powf(self.magnitude.max(f64::MIN_POSITIVE).log2().floor()) * epsilon
+    }
+
+    pub fn steps_per_pixel(&self, precision: Precision) -> f64 {
+        self.pixel_spacing / self.ulp(precision)
+    }
+
+    pub fn resolution(&self, precision: Precision) -> Resolution {
+        let steps = self.steps_per_pixel(precision);
+        if steps >= MIN_STEPS_PER_PIXEL {
+            Resolution::Full
+        } else if steps >= 1.0 {
+            Resolution::Degraded
+        } else {
+            Resolution::Lost
+        }
+    }
+}
+
+
+fn cheapest_combination(
+    analysis: &PrecisionAnalysis,
+    backends: &[BackendType],
+    resolution: Resolution
+) -> Option<(BackendType, Precision)> {
+    backends.iter().find_map(|backend| {
+        PRECISIONS_BY_COST.iter()
+            .find(|p| backend.supports_precision(**p) && analysis.resolution(**p) >= resolution)
+            .map(|p| (*backend, *p))
+    })
+}
+
+
+pub fn select_precision(params: &mut Parameters, policy: &PrecisionPolicy) -> Result<(), Box<dyn std::error::Error>> {
+    let analysis = PrecisionAnalysis::new(params);
+    if analysis.pixel_spacing <= 0.0 || !analysis.pixel_spacing.is_finite() {
+        return Err("View limits must describe a non-empty area".into());
+    }
+
+    tracing::debug!("Pixel spacing {:e} at magnitude {:e}", analysis.pixel_spacing, analysis.magnitude);
+
+    if !params.backend_type.supports_precision(params.precision) {
+        return Err(format!("Backend {:?} does not support {:?} precision", params.backend_type, params.precision).into());
+    }
+
+    if policy.automatic {
+        let own_backend = [params.backend_type];
+        let selected = [Resolution::Full, Resolution::Degraded].iter().find_map(|resolution| {
+            cheapest_combination(&analysis, &own_backend, *resolution).or_else(|| {
+                policy.auto_backend.then(|| cheapest_combination(&analysis, &BACKENDS_BY_COST, *resolution)).flatten()
+            })
+        });
+
+        if let Some((backend, precision)) = selected {
+            if backend != params.backend_type {
+                tracing::warn!("Backend {:?} cannot resolve the view, switching to {backend:?}", params.backend_type);
+            }
+            params.backend_type = backend;
+            params.precision = precision;
+        } else if let Some(precision) = PRECISIONS_BY_COST.iter().rev().find(|p| params.backend_type.supports_precision(**p)) {
+            params.precision = *precision;
+        }
+        tracing::info!("Selected {:?} precision", params.precision);
+    }
+
+    let steps = analysis.steps_per_pixel(params.precision);
+    match analysis.resolution(params.precision) {
+        Resolution::Full => (),
+        Resolution::Degraded =>
+            tracing::warn!("{:?} precision resolves only {steps:.1} steps per pixel, supersampling will be inaccurate", params.precision),
+        Resolution::Lost if policy.allow_precision_loss =>
+            tracing::warn!("{:?} precision cannot resolve the view ({steps:.2} steps per pixel), image will be blocky", params.precision),
+        Resolution::Lost =>
+            return Err(format!(
+                "{:?} precision cannot resolve the view ({steps:.2} steps per pixel), \
+                 use a larger view, --precision, --auto-backend or --allow-precision-loss",
+                params.precision
+            ).into())
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::parameters::{parse_params, BackendType, Precision};
+
+    // view 1e-9 wide at -0.75, single precision cannot tell its pixels apart
+    const DEEP_ZOOM: [&str; 5] = ["--", "-0.750000001", "-0.749999999", "0.1", "0.100000002"];
+
+
+    fn view_args<'a>(backend: &'a str, options: &[&'a str], view: &[&'a str]) -> Vec<&'a str> {
+        [&[backend, "64", "100"][..], options, view].concat()
+    }
+
+
+    #[test]
+    fn single_precision_resolves_whole_set() {
+        let params = parse_params(&view_args("cpu-par-iter", &[], &["--", "-2.2", "0.8", "-1.5", "1.5"])).unwrap();
+        assert_eq!(params.precision, Precision::Single);
+    }
+
+
+    #[test]
+    fn deep_zoom_selects_double_precision() {
+        let params = parse_params(&view_args("cpu-par-iter", &[], &DEEP_ZOOM)).unwrap();
+        assert_eq!(params.precision, Precision::Double);
+    }
+
+
+    #[test]
+    fn deep_zoom_is_refused_in_single_precision() {
+        assert!(parse_params(&view_args("opengl-glsl", &[], &DEEP_ZOOM)).is_err());
+        assert!(parse_params(&view_args("cpu-par-iter", &["--precision", "single"], &DEEP_ZOOM)).is_err());
+
+        let params = parse_params(&view_args("opengl-glsl", &["--allow-precision-loss"], &DEEP_ZOOM)).unwrap();
+        assert_eq!((params.backend_type, params.precision), (BackendType::OpenglGlsl, Precision::Single));
+    }
+
+
+    #[test]
+    fn hybrid_is_not_preferred_to_single_gpu() {
+        let position = |backend| super::BACKENDS_BY_COST.iter().position(|b| *b == backend).unwrap();
+        assert!(super::BACKENDS_BY_COST.iter().filter(|b| b.is_gpu()).all(|b| position(*b) < position(BackendType::Hybrid)));
+    }
+
+
+    #[test]
+    fn deep_zoom_switches_to_double_precision_backend() {
+        let params = parse_params(&view_args("opengl-glsl", &["--auto-backend"], &DEEP_ZOOM)).unwrap();
+        assert!(params.backend_type.supports_precision(Precision::Double));
+        assert_eq!(params.precision, Precision::Double);
+    }
+}
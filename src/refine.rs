@@ -0,0 +1,177 @@
+use crate::backends::cpu::mandelbrot_for_xy;
+use crate::parameters::{BackendType, Parameters};
+use crate::result::ComputeResult;
+
+use rayon::prelude::*;
+use std::ops::Range;
+
+
+// every pass doubles the iteration budget, this limits it to max_iter * 2^MAX_PASSES
+const MAX_PASSES: u32 = 8;
+
+
+// saturated points of a width x height grid with at least one escaping point among their 8 neighbours
+pub fn saturated_boundary(width: usize, height: usize, saturated: impl Fn(usize) -> bool + Sync) -> Vec<usize> {
+    (0..width * height).into_par_iter().filter(|i| {
+        if !saturated(*i) {
+            return false;
+        }
+        let (x, y) = ((i % width) as isize, (i / width) as isize);
+        (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .filter(|(nx, ny)| *nx >= 0 && *ny >= 0 && (*nx as usize) < width && (*ny as usize) < height)
+            .any(|(nx, ny)| !saturated(ny as usize * width + nx as usize))
+    }).collect()
+}
+
+
+// rows of the image holding any of the pixels of a grid of the given width, merged into ranges of consecutive rows;
+// pixels are in ascending order
+fn row_ranges(pixels: &[usize], width: usize, first_row: u16) -> Vec<Range<u16>> {
+    let mut ranges: Vec<Range<u16>> = Vec::new();
+    for row in pixels.iter().map(|i| first_row + (i / width) as u16) {
+        match ranges.last_mut() {
+            Some(range) if row < range.end => (),
+            Some(range) if row == range.end => range.end += 1,
+            _ => ranges.push(row..row + 1)
+        }
+    }
+    ranges
+}
+
+
+// index of a refined pixel, its value and the color of the device, if it applied the colormap
+type Escaped = (usize, u8, Option<[u8; 4]>);
+
+
+// renders the rows of the candidates with the budget of the params, returns the candidates which escaped
+fn render_candidate_rows(
+    params: &Parameters,
+    candidates: &[usize],
+    render_rows: &mut impl FnMut(&Parameters) -> Result<ComputeResult, Box<dyn std::error::Error>>
+) -> Result<Vec<Escaped>, Box<dyn std::error::Error>> {
+    let size = params.img_size_px as usize;
+    let mut escaped = Vec::new();
+    for rows in row_ranges(candidates, size, params.rows.start) {
+        let band = render_rows(&Parameters { rows: rows.clone(), ..params.clone() })?;
+        let offset = (rows.start - params.rows.start) as usize * size;
+        let band_candidates = candidates.partition_point(|i| *i < offset)..
+            candidates.partition_point(|i| *i < offset + band.data.len());
+        escaped.extend(candidates[band_candidates].iter()
+            .filter(|i| band.data[*i - offset] != 0)
+            .map(|i| (*i, band.data[i - offset], band.colors.as_ref().map(|colors| colors[i - offset]))));
+    }
+    Ok(escaped)
+}
+
+
+// Renders saturated pixels next to escaping ones again with growing budget, until fewer than `refine_threshold` of
+// them turn out to escape. The CPU backends compute every pixel on its own and iterate only these pixels, the GPU and
+// hybrid ones render their rows with `render_rows`.
+pub fn refine(
+    params: &Parameters,
+    result: &mut ComputeResult,
+    mut render_rows: impl FnMut(&Parameters) -> Result<ComputeResult, Box<dyn std::error::Error>>
+) -> Result<(), Box<dyn std::error::Error>> {
+    let size = params.img_size_px as usize;
+    let budget_limit = params.max_iter.saturating_mul(1 << MAX_PASSES).min(crate::parameters::MAX_ITER_LIMIT);
+    let mut budget = params.max_iter;
+
+    while budget < budget_limit {
+        budget = budget.saturating_mul(2).min(budget_limit);
+        let budget_params = Parameters { iteration_budget: Some(budget), ..params.clone() };
+
+        // value 0 is left for the pixels which did not escape
+        let candidates = saturated_boundary(size, result.data.len() / size, |i| result.data[i] == 0);
+        let escaped = if params.backend_type.is_gpu() || params.backend_type == BackendType::Hybrid {
+            render_candidate_rows(&budget_params, &candidates, &mut render_rows)?
+        } else {
+            candidates.par_iter().filter_map(|i| {
+                let value = mandelbrot_for_xy((i % size) as u16, params.rows.start + (i / size) as u16, &budget_params);
+                (value != 0).then_some((*i, value, None))
+            }).collect()
+        };
+
+        for (i, value, color) in escaped.iter() {
+            result.data[*i] = *value;
+            if let Some(colors) = result.colors.as_mut() {
+                colors[*i] = color.unwrap_or_else(|| crate::colormap::rgba(crate::colormap::inferno()[*value as usize]));
+            }
+        }
+        tracing::debug!("Refinement with {budget} iterations: {} of {} saturated pixels changed", escaped.len(), candidates.len());
+
+        if escaped.len() < params.refine_threshold {
+            break;
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::backends::cpu::run_cpu_par_iter;
+    use crate::parameters::{parse_params, BackendType, Parameters};
+
+    use super::{refine, row_ranges, saturated_boundary};
+
+
+    #[test]
+    fn boundary_includes_diagonal_neighbours() {
+        // saturated 3x3 block in the top left corner of a 4x4 grid
+        let boundary = saturated_boundary(4, 4, |i| i % 4 < 3 && i / 4 < 3);
+        assert_eq!(boundary, vec![2, 6, 8, 9, 10]);
+    }
+
+
+    #[test]
+    fn merges_rows_of_candidates() {
+        // rows 0, 1 and 3 of a 4 pixels wide band starting at row 10
+        assert_eq!(row_ranges(&[1, 2, 5, 13], 4, 10), vec![10..12, 13..14]);
+        assert!(row_ranges(&[], 4, 10).is_empty());
+    }
+
+
+    #[test]
+    fn refines_only_saturated_pixels() {
+        // budget far too low for the boundary of the set
+        let params = parse_params(&["cpu-par-iter", "64", "20", "--refine", "--", "-2.2", "0.8", "-1.5", "1.5"]).unwrap();
+        let before = run_cpu_par_iter(&params).data;
+        let mut result = run_cpu_par_iter(&params);
+        refine(&params, &mut result, |params| Ok(run_cpu_par_iter(params))).unwrap();
+
+        let changed: Vec<usize> = (0..before.len()).filter(|i| before[*i] != result.data[*i]).collect();
+        assert!(!changed.is_empty());
+        assert!(changed.iter().all(|i| before[*i] == 0 && result.data[*i] != 0));
+    }
+
+
+    #[test]
+    fn early_escapes_are_not_saturated() {
+        // every point escapes within a few iterations, far fewer than a value step
+        let params = parse_params(&["cpu-par-iter", "64", "100000", "--refine", "--", "-2.2", "-2.1", "1.0", "1.1"]).unwrap();
+        let mut result = run_cpu_par_iter(&params);
+        assert!(result.data.iter().all(|v| *v != 0));
+
+        let before = result.data.clone();
+        refine(&params, &mut result, |params| Ok(run_cpu_par_iter(params))).unwrap();
+        assert!(result.data == before);
+
+        // without refinement they keep the value they always had
+        let params = Parameters { refine_threshold: 0, ..params };
+        assert!(run_cpu_par_iter(&params).data.contains(&0));
+    }
+
+
+    #[test]
+    fn rendered_rows_refine_as_single_pixels() {
+        let params = parse_params(&["cpu-par-iter", "64", "20", "--refine", "--", "-2.2", "0.8", "-1.5", "1.5"]).unwrap();
+        let mut pixels = run_cpu_par_iter(&params);
+        refine(&params, &mut pixels, |_| panic!("CPU backends refine single pixels")).unwrap();
+
+        // rows of the hybrid backend rendered by cpu-par-iter
+        let params = Parameters { backend_type: BackendType::Hybrid, ..params };
+        let mut rows = run_cpu_par_iter(&params);
+        refine(&params, &mut rows, |params| Ok(run_cpu_par_iter(params))).unwrap();
+        assert!(rows.data == pixels.data);
+    }
+}
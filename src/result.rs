@@ -1,7 +1,41 @@
 
 pub struct ComputeResult {
     pub data: Vec<u8>,
+    // RGBA colors of the pixels, when the samples were averaged as colors or the device applied the colormap
+    pub colors: Option<Vec<[u8; 4]>>,
+    // iterations run for every pixel, summed over its samples, by the backends counting them for the cost map
+    pub costs: Option<Vec<u32>>,
     pub initialization_time: std::time::Duration,
     pub computation_time: std::time::Duration,
+    // time measured on the CPU around device work which could not be timed on the device itself
+    pub computation_time_estimated: bool,
     pub data_fetch_time: std::time::Duration
 }
+
+
+fn stitch_rows<'a, P: Copy + Default + 'a>(
+    params: &crate::parameters::Parameters,
+    bands: impl Iterator<Item = (&'a std::ops::Range<u16>, &'a [P])>
+) -> Vec<P> {
+    let row_len = params.img_size_px as usize;
+    let mut data = vec![P::default(); row_len * params.rows.len()];
+    for (rows, band) in bands {
+        let offset = (rows.start - params.rows.start) as usize * row_len;
+        data[offset..offset + band.len()].copy_from_slice(band);
+    }
+    data
+}
+
+
+// joins bands of rows rendered separately into the data of all the requested rows, colors are joined only when
+// every band has them
+pub fn stitch_bands(
+    params: &crate::parameters::Parameters,
+    bands: &[&(std::ops::Range<u16>, ComputeResult)]
+) -> (Vec<u8>, Option<Vec<[u8; 4]>>) {
+    let data = stitch_rows(params, bands.iter().map(|(rows, result)| (rows, &result.data[..])));
+    let colors = bands.iter().all(|(_, result)| result.colors.is_some()).then(|| {
+        stitch_rows(params, bands.iter().map(|(rows, result)| (rows, &result.colors.as_ref().unwrap()[..])))
+    });
+    (data, colors)
+}
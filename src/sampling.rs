@@ -0,0 +1,98 @@
+use crate::backends::cpu::{mandelbrot_for_xy, shaded_pixel_for_xy};
+use crate::colormap::{inferno, rgba};
+use crate::parameters::{Parameters, SamplePattern, Sampling};
+use crate::result::ComputeResult;
+
+use rayon::prelude::*;
+
+
+// integer hash (splitmix64 finalizer) mapped to [0, 1), keeps the jittered pattern the same between runs
+fn hash_to_unit(v: u64) -> f32 {
+    let mut v = v.wrapping_add(0x9e3779b97f4a7c15);
+    v = (v ^ (v >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    v = (v ^ (v >> 27)).wrapping_mul(0x94d049bb133111eb);
+    v ^= v >> 31;
+    (v >> 40) as f32 / (1u64 << 24) as f32
+}
+
+
+// offsets of the samples from the pixel coordinates, in pixels
+pub fn sample_offsets(count: u16, pattern: SamplePattern) -> Vec<[f32; 2]> {
+    let side = (count as f32).sqrt() as u16;
+    assert_eq!(side * side, count);
+    let side_f = side as f32;
+
+    (0..side).flat_map(|j| (0..side).map(move |i| (i, j))).map(|(i, j)| {
+        let (u, v) = ((i as f32 + 0.5) / side_f - 0.5, (j as f32 + 0.5) / side_f - 0.5);
+        match pattern {
+            SamplePattern::Grid => [u, v],
+            // one sample at random position within every cell of the grid
+            SamplePattern::Jittered => {
+                let cell = (j * side + i) as u64;
+                [u + (hash_to_unit(2 * cell) - 0.5) / side_f, v + (hash_to_unit(2 * cell + 1) - 0.5) / side_f]
+            },
+            // grid rotated by atan(1/2) and scaled by sqrt(5)/2, wrapped back into the pixel, which keeps the samples
+            // on distinct rows and columns
+            SamplePattern::RotatedGrid => {
+                let (x, y) = (u - v / 2.0, u / 2.0 + v);
+                [x - x.round(), y - y.round()]
+            }
+        }
+    }).collect()
+}
+
+
+// with adaptive sampling renders one sample per pixel and supersamples only the pixels which differ from any of their
+// neighbours, on the CPU
+pub fn render(
+    params: &Parameters,
+    mut render_rows: impl FnMut(&Parameters) -> Result<ComputeResult, Box<dyn std::error::Error>>
+) -> Result<ComputeResult, Box<dyn std::error::Error>> {
+    if !params.sampling.adaptive || params.sampling.offsets.len() == 1 {
+        return render_rows(params);
+    }
+
+    let single_params = Parameters {
+        sampling: Sampling { offsets: vec![[0.0, 0.0]], ..params.sampling.clone() },
+        ..params.clone()
+    };
+    let mut result = render_rows(&single_params)?;
+
+    let start_time = std::time::Instant::now();
+    let width = params.img_size_px as usize;
+    let data = &result.data;
+    let height = data.len() / width;
+    let edges: Vec<usize> = (0..data.len()).into_par_iter().filter(|i| {
+        let (x, y) = (i % width, i / width);
+        [
+            (x > 0).then(|| i - 1),
+            (x + 1 < width).then(|| i + 1),
+            (y > 0).then(|| i - width),
+            (y + 1 < height).then(|| i + width)
+        ].iter().flatten().any(|n| data[*n] != data[*i])
+    }).collect();
+
+    let coordinates = |i: usize| ((i % width) as u16, params.rows.start + (i / width) as u16);
+    let pixels: Vec<(u8, [u8; 4])> = edges.par_iter().map(|i| {
+        let (x, y) = coordinates(*i);
+        if params.sampling.color_average {
+            shaded_pixel_for_xy(x, y, params)
+        } else {
+            let value = mandelbrot_for_xy(x, y, params);
+            (value, rgba(inferno()[value as usize]))
+        }
+    }).collect();
+    for (i, (value, color)) in edges.iter().zip(pixels) {
+        result.data[*i] = value;
+        // colors applied by the device are replaced as well
+        if let Some(colors) = result.colors.as_mut() {
+            colors[*i] = color;
+        }
+    }
+    tracing::debug!("Adaptive sampling: {} of {} pixels supersampled", edges.len(), result.data.len());
+
+    Ok(ComputeResult {
+        computation_time: result.computation_time + start_time.elapsed(),
+        ..result
+    })
+}
@@ -0,0 +1,30 @@
+
+// colormap as RGBA8 words
+@group(0)
+@binding(0)
+var<storage, read> lut: array<u32, 256>;
+
+// values of the pixels, 4 per word, as written by the mandelbrot shaders
+@group(0)
+@binding(1)
+var<storage, read> v_pixels: array<u32>;
+
+// RGBA8 colors of the pixels, one per word
+@group(0)
+@binding(2)
+var<storage, read_write> v_colors: array<u32>;
+
+
+// one invocation colorizes a word of values, the dispatch covers one row of the image per workgroup row
+@compute
+@workgroup_size(16)
+fn main(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    var index = global_id.y * num_workgroups.x * 16u + global_id.x;
+    var values = v_pixels[index];
+    for (var i = 0u; i < 4u; i += 1u) {
+        v_colors[index * 4u + i] = lut[(values >> (i * 8u)) & 255u];
+    }
+}
@@ -0,0 +1,188 @@
+use std::path::Path;
+
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+
+use crate::parameters::BackendType;
+
+
+const SPIRV_MAGIC: u32 = 0x07230203;
+
+
+// shader given on the command line in place of the built-in mandelbrot shader, checked when loaded
+#[derive(Debug)]
+pub enum Shader {
+    Wgsl(String),
+    Spirv(Vec<u32>)
+}
+
+
+fn render_diagnostic(path: &Path, source: &str, diagnostic: &Diagnostic<()>) -> String {
+    let file = codespan_reporting::files::SimpleFile::new(path.display().to_string(), source);
+    let mut writer = termcolor::NoColor::new(Vec::new());
+    codespan_reporting::term::emit(&mut writer, &Default::default(), &file, diagnostic).unwrap();
+    String::from_utf8(writer.into_inner()).unwrap()
+}
+
+
+// validation errors wrap their cause, e.g. the function and then the expression in it which is invalid
+pub fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message += &format!(": {cause}");
+        source = cause.source();
+    }
+    message
+}
+
+
+fn load_wgsl(path: &Path) -> Result<(naga::Module, Shader), String> {
+    let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+
+    let module = naga::front::wgsl::parse_str(&source).map_err(|error| {
+        let labels = error.labels()
+            .map(|(span, description)| Label::primary((), span).with_message(description))
+            .collect();
+        render_diagnostic(path, &source, &Diagnostic::error().with_message(error.message()).with_labels(labels))
+    })?;
+
+    crate::shaders::validation::validate(&module).map_err(|error| {
+        let labels = error.spans()
+            .filter_map(|(span, description)| span.to_range().map(|range| Label::primary((), range).with_message(description)))
+            .collect();
+        render_diagnostic(path, &source, &Diagnostic::error().with_message(error_chain(&error)).with_labels(labels))
+    })?;
+
+    Ok((module, Shader::Wgsl(source)))
+}
+
+
+// naga does not cover all of SPIR-V, e.g. the 8-bit integers of the u8 shader, when the module goes straight to an
+// OpenGL driver its errors are only warnings and the driver has the last word
+fn load_spirv(path: &Path, passthrough: bool) -> Result<(Option<naga::Module>, Shader), String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    if bytes.len() % 4 != 0 || bytes.get(..4) != Some(&SPIRV_MAGIC.to_le_bytes()) {
+        return Err(format!("{}: not a little-endian SPIR-V module", path.display()));
+    }
+    let words = bytes.chunks_exact(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect();
+
+    // there is no source to point to, errors are reported with their causes only
+    let options = naga::front::spv::Options {
+        adjust_coordinate_space: false,
+        strict_capabilities: false,
+        block_ctx_dump_prefix: None
+    };
+    let module = naga::front::spv::parse_u8_slice(&bytes, &options)
+        .map_err(|error| format!("{}: invalid SPIR-V: {}", path.display(), error_chain(&error)))
+        .and_then(|module| match crate::shaders::validation::validate(&module) {
+            Ok(_) => Ok(module),
+            Err(error) => Err(format!("{}: {}", path.display(), error_chain(&error)))
+        });
+
+    match module {
+        Ok(module) => Ok((Some(module), Shader::Spirv(words))),
+        Err(error) if passthrough => {
+            tracing::warn!("{error}, the shader is passed to the driver unchecked");
+            Ok((None, Shader::Spirv(words)))
+        }
+        Err(error) => Err(error)
+    }
+}
+
+
+// reads and validates the shader the backend will run instead of the built-in one, WGSL or SPIR-V by the extension
+pub fn load(path: &Path, backend_type: BackendType) -> Result<Shader, String> {
+    let spirv_backend = match backend_type {
+        BackendType::WgpuWgsl | BackendType::OpenglGlsl | BackendType::OpenglFragment => false,
+        BackendType::WgpuSpirv | BackendType::OpenglSpirv | BackendType::OpenglSpirvU8 => true,
+        backend_type => return Err(format!("Backend {backend_type:?} does not run shaders"))
+    };
+
+    let (module, shader) = match path.extension().and_then(|e| e.to_str()) {
+        Some("wgsl") if !spirv_backend => {
+            let (module, shader) = load_wgsl(path)?;
+            (Some(module), shader)
+        }
+        Some("spv") if spirv_backend =>
+            load_spirv(path, matches!(backend_type, BackendType::OpenglSpirv | BackendType::OpenglSpirvU8))?,
+        Some("wgsl" | "spv") =>
+            return Err(format!("{}: backend {backend_type:?} runs {} shaders", path.display(), if spirv_backend { "SPIR-V" } else { "WGSL" })),
+        _ => return Err(format!("{}: unknown shader format, expected a .wgsl or .spv file", path.display()))
+    };
+
+    // the backends look the entry points up by these names
+    let entry_points: &[(naga::ShaderStage, &str)] = match backend_type {
+        BackendType::OpenglFragment => &[(naga::ShaderStage::Vertex, "vs_main"), (naga::ShaderStage::Fragment, "fs_main")],
+        _ => &[(naga::ShaderStage::Compute, "main")]
+    };
+    if let Some(module) = module {
+        for &(stage, name) in entry_points {
+            if !module.entry_points.iter().any(|entry_point| entry_point.stage == stage && entry_point.name == name) {
+                return Err(format!("{}: no {stage:?} entry point named {name}", path.display()));
+            }
+        }
+        crate::shaders::provider::check_interface(&module).map_err(|error| format!("{}: {error}", path.display()))?;
+    }
+
+    // the OpenGL backends run the GLSL translation, which naga may not be able to write
+    if let Shader::Wgsl(source) = &shader {
+        crate::shaders::provider::check_template(source).map_err(|error| format!("{}: {error}", path.display()))?;
+        let versions = match backend_type {
+            BackendType::OpenglGlsl => vec![crate::shaders::provider::GLSL_COMPUTE_VERSION],
+            BackendType::OpenglFragment =>
+                vec![crate::shaders::provider::fragment_glsl_version(false), crate::shaders::provider::fragment_glsl_version(true)],
+            _ => vec![]
+        };
+        for version in versions {
+            for &(stage, name) in entry_points {
+                crate::shaders::provider::wgsl_to_glsl(source, version, stage, name)
+                    .map_err(|error| format!("{}: {error}", path.display()))?;
+            }
+        }
+    }
+
+    Ok(shader)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::parameters::BackendType;
+
+    use super::load;
+
+
+    fn load_wgsl_source(name: &str, source: &str, backend_type: BackendType) -> Result<super::Shader, String> {
+        let path = std::env::temp_dir().join(format!("mandelbrot-{name}-{}.wgsl", std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        let shader = load(&path, backend_type);
+        std::fs::remove_file(&path).unwrap();
+        shader
+    }
+
+
+    #[test]
+    fn loads_built_in_shader() {
+        for backend_type in [BackendType::WgpuWgsl, BackendType::OpenglGlsl, BackendType::OpenglFragment] {
+            load_wgsl_source("built-in", include_str!("mandelbrot.wgsl"), backend_type).unwrap();
+        }
+    }
+
+
+    #[test]
+    fn rejects_shaders_the_buffers_do_not_fit() {
+        let source = include_str!("mandelbrot.wgsl");
+        let rebound = source.replace("@binding(1)", "@binding(2)");
+        assert!(load_wgsl_source("rebound", &rebound, BackendType::OpenglGlsl).unwrap_err().contains("binding 1"));
+        let padded = source.replace("\n    max_iter: u32,", "\n    max_iter: u32,\n    padding: u32,");
+        assert!(load_wgsl_source("padded", &padded, BackendType::WgpuWgsl).unwrap_err().contains("Uniform members"));
+    }
+
+
+    // the built-in shaders have no optional capabilities, the replacements are held to the same
+    #[test]
+    fn rejects_capabilities_of_some_backends_only() {
+        let source = format!("{}\nvar<private> wide: f64;\n", include_str!("mandelbrot.wgsl"));
+        assert!(load_wgsl_source("f64", &source, BackendType::WgpuWgsl).unwrap_err().contains("width 8 is not supported"));
+    }
+}
@@ -1,8 +1,11 @@
 
 struct InputParameters {
     draw_bounds: vec4<f32>, // -x, x, -y, y
-    max_iter: u32,          // max number of iterations to run
-    img_size_px: u32        // in pixels
+    max_iter: u32,          // number of iterations mapped to the brightest value
+    img_size_px: u32,       // in pixels
+    flags: u32,             // 1 - main cardioid and period-2 bulb check, 2 - periodicity check, 4 - value 0 only when no sample escaped
+    budget: u32,            // max number of iterations to run
+    sample_offsets: array<vec4<f32>, 32> // offsets of samples in pixels, two per element
 }
 
 @group(0)
@@ -15,13 +18,39 @@ var<storage, write> v_pixels: array<u32>;
 
 var<private> img_size: f32;
 
+// template constants, the backends write the values of the parameters in place of these defaults
+// pixels of a row computed by one invocation, a multiple of the 4 pixels packed into a word of v_pixels
+let PIXELS_PER_INVOCATION: u32 = 4u;
+// samples per pixel, input_parameters.sample_offsets holds their offsets
+let SAMPLE_COUNT: u32 = 16u;
+
+// analytic test for the main cardioid and the period-2 bulb
+fn inside_main_bulbs(c: vec2<f32>) -> bool {
+    var xq = c[0] - 0.25f;
+    var q = xq*xq + c[1]*c[1];
+    var xb = c[0] + 1.0f;
+    return q * (q + xq) <= 0.25f * c[1]*c[1] || xb*xb + c[1]*c[1] <= 0.0625f;
+}
+
+
 // c - coordinates of complex point to check
-// returns 0 if point inside set, otherwise number of iterations (up to max_iter) necessary to escape the set for sure
+// returns 0 if point inside set, otherwise number of iterations (up to budget) necessary to escape the set for sure
 fn mandelbrot(c: vec2<f32>) -> u32 {
+    if ((input_parameters.flags & 1u) != 0u && inside_main_bulbs(c)) {
+        return 0u;
+    }
+    var periodicity_check = (input_parameters.flags & 2u) != 0u;
+
     // mandelbrot
     // z_n+1 = z_n * z_n + c
     var z = vec2(0.0f, 0.0f);
-    for (var i = 0u; i < input_parameters.max_iter; i += 1u) {
+
+    // Brent's cycle detection: orbit is compared with a point saved at power of two steps
+    var saved = z;
+    var period = 1u;
+    var steps = 0u;
+
+    for (var i = 0u; i < input_parameters.budget; i += 1u) {
         if (length(z) > 2.0f) {
             return i;
         }
@@ -29,58 +58,52 @@ fn mandelbrot(c: vec2<f32>) -> u32 {
             z[0]*z[0] - z[1]*z[1],
             z[0]*z[1] + z[1]*z[0]
         ) + c;
+
+        if (periodicity_check) {
+            // cycling orbit never escapes
+            if (all(z == saved)) {
+                return 0u;
+            }
+            steps += 1u;
+            if (steps == period) {
+                saved = z;
+                steps = 0u;
+                period *= 2u;
+            }
+        }
     }
     return 0u;
 }
 
 
-fn mandelmsaax4(c: vec2<f32>) -> u32 {
-    // distribution with heavier middle
-    var dpx = 1.0f / 4.0f / img_size;
-    var sum =
-        mandelbrot(c) +
-        mandelbrot(c + vec2(-dpx, -dpx)) +
-        mandelbrot(c + vec2( dpx, -dpx)) +
-        mandelbrot(c + vec2( dpx,  dpx)) +
-        mandelbrot(c + vec2(-dpx,  dpx));
-    return sum / 5u;
-}
-
-
-fn mandelmsaax16(c: vec2<f32>) -> u32 {
-    // uniform distribution of 16 points across pixel
-    var dpx = 1.0f / 8.0f / img_size;
-    var dpx2 = 3.0f / 8.0f / img_size;
-
-    var sum =
-        mandelbrot(c + vec2(-dpx2, -dpx2)) +
-        mandelbrot(c + vec2(-dpx,  -dpx2)) +
-        mandelbrot(c + vec2( dpx,  -dpx2)) +
-        mandelbrot(c + vec2( dpx2, -dpx2)) +
-
-        mandelbrot(c + vec2(-dpx2, -dpx)) +
-        mandelbrot(c + vec2(-dpx,  -dpx)) +
-        mandelbrot(c + vec2( dpx,  -dpx)) +
-        mandelbrot(c + vec2( dpx2, -dpx)) +
-
-        mandelbrot(c + vec2(-dpx2, dpx)) +
-        mandelbrot(c + vec2(-dpx,  dpx)) +
-        mandelbrot(c + vec2( dpx,  dpx)) +
-        mandelbrot(c + vec2( dpx2, dpx)) +
-
-        mandelbrot(c + vec2(-dpx2, dpx2)) +
-        mandelbrot(c + vec2(-dpx,  dpx2)) +
-        mandelbrot(c + vec2( dpx,  dpx2)) +
-        mandelbrot(c + vec2( dpx2, dpx2));
-
-    return sum / 16u;
+fn mandelsamples(c: vec2<f32>) -> u32 {
+    var sum = 0u;
+    for (var i = 0u; i < SAMPLE_COUNT; i += 1u) {
+        var offsets = input_parameters.sample_offsets[i / 2u];
+        var offset = select(offsets.xy, offsets.zw, i % 2u == 1u);
+        sum += mandelbrot(c + offset / img_size);
+    }
+    // with flag 4 rounded up, so that the average is 0 only when none of the samples escaped
+    if ((input_parameters.flags & 4u) != 0u) {
+        return (sum + SAMPLE_COUNT - 1u) / SAMPLE_COUNT;
+    }
+    return sum / SAMPLE_COUNT;
 }
 
 
+// with flag 4 0 is left for pixels which did not escape, the ones which escaped early are at least 1
 fn mandelproc(c: vec2<f32>) -> u32 {
+    var iterations = mandelsamples(c);
+    var lowest = 0.0f;
+    if ((input_parameters.flags & 4u) != 0u) {
+        if (iterations == 0u) {
+            return 0u;
+        }
+        lowest = 1.0f;
+    }
     return u32(clamp(
-        f32(mandelmsaax16(c)) / f32(input_parameters.max_iter) * 1.5f * 255.0f,
-        0.0f,
+        f32(iterations) / f32(input_parameters.max_iter) * 1.5f * 255.0f,
+        lowest,
         255.0f
     ));
 }
@@ -95,21 +118,67 @@ fn get_calc_x(pixel: u32) -> f32 {
 }
 
 
-@compute
-@workgroup_size(16, 16)
-fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    img_size = f32(input_parameters.img_size_px);
-
-    var img_x = global_id.x * 4u;
-    var img_y_f = mix(
+fn get_calc_y(row: u32) -> f32 {
+    return mix(
         input_parameters.draw_bounds[2],
         input_parameters.draw_bounds[3],
-        f32(global_id.y) / img_size
+        f32(row) / img_size
     );
+}
+
+
+// values of 4 consecutive pixels of the row packed into u32, first pixel in the lowest byte
+fn mandelpixels(id: vec2<u32>) -> u32 {
+    img_size = f32(input_parameters.img_size_px);
 
-    v_pixels[global_id.y * (input_parameters.img_size_px / 4u) + global_id.x] =
+    var img_x = id.x * 4u;
+    var img_y_f = get_calc_y(id.y);
+
+    return
         mandelproc(vec2(get_calc_x(img_x     ), img_y_f))        |
         mandelproc(vec2(get_calc_x(img_x + 1u), img_y_f)) << 8u  |
         mandelproc(vec2(get_calc_x(img_x + 2u), img_y_f)) << 16u |
         mandelproc(vec2(get_calc_x(img_x + 3u), img_y_f)) << 24u;
 }
+
+
+@compute
+@workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    var words = PIXELS_PER_INVOCATION / 4u;
+    for (var i = 0u; i < words; i += 1u) {
+        var word = global_id.x * words + i;
+        v_pixels[global_id.y * (input_parameters.img_size_px / 4u) + word] = mandelpixels(vec2(word, global_id.y));
+    }
+}
+
+
+// one pixel per element of v_pixels, for the Int8 SPIR-V variant generated by build.rs which turns v_pixels into an
+// array of bytes, any pixels per invocation
+@compute
+@workgroup_size(16, 16)
+fn main_u8(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    img_size = f32(input_parameters.img_size_px);
+
+    var img_y_f = get_calc_y(global_id.y);
+    for (var i = 0u; i < PIXELS_PER_INVOCATION; i += 1u) {
+        var x = global_id.x * PIXELS_PER_INVOCATION + i;
+        v_pixels[global_id.y * input_parameters.img_size_px + x] = mandelproc(vec2(get_calc_x(x), img_y_f));
+    }
+}
+
+
+// full-screen triangle of the fragment shader path
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    var uv = vec2(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4(uv * 2.0f - 1.0f, 0.0f, 1.0f);
+}
+
+
+// one fragment of an RGBA8 target holds 4 consecutive pixels, the same bytes as a word of v_pixels
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    var pixels = mandelpixels(vec2<u32>(position.xy));
+    return vec4<f32>(vec4(pixels, pixels >> 8u, pixels >> 16u, pixels >> 24u) & vec4(255u)) / 255.0f;
+}
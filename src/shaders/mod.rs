@@ -1 +1,4 @@
+pub mod loader;
 pub mod provider;
+pub mod spirv;
+pub mod validation;
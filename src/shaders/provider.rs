@@ -1,133 +1,516 @@
 use crate::parameters::BackendType;
+use crate::shaders::loader::Shader;
 
 
-pub fn get_spirv_configuration(params: &crate::parameters::Parameters)
--> ((u32, u32, u32), Vec<u8>) {
-    const WORKGROUP_SIZE: u16 = 16;
-    // processing must be divided into full workgroups
-    assert_eq!(params.img_size_px % WORKGROUP_SIZE, 0);
-    let no_groups = params.img_size_px / WORKGROUP_SIZE;
-    // one shader invocation processes 4 consecutive pixels (due to stupid wgsl limitation of working only on u32)
-    assert_eq!(no_groups % 4, 0);
+// workgroup width of the colorization shader
+const COLORIZE_WORKGROUP_SIZE: u16 = 16;
 
-    #[repr(C)]
-    struct InputParameters {
-        draw_bounds: [f32; 4], // -x, x, -y, y
-        img_size_px: u32,      // in pixels
-        max_iter: u32          // max number of iterations to ru
-    }
+// samples packed two per vec4<f32> of the uniform
+const MAX_SAMPLES: usize = 64;
 
-    let input_parameters = InputParameters {
-        draw_bounds: params.limits,
-        img_size_px: params.img_size_px as u32,
-        max_iter: params.max_iter as u32
-    };
+// GLSL translations of the compute shaders
+pub const GLSL_COMPUTE_VERSION: naga::back::glsl::Version = naga::back::glsl::Version::Desktop(430);
 
-    let input_params_as_bytes = unsafe { std::slice::from_raw_parts(
-        (&input_parameters as *const InputParameters) as *const u8,
-        std::mem::size_of::<InputParameters>()
-    )}.to_vec();
+const BULB_CHECK_FLAG: u32 = 1;
+const PERIODICITY_CHECK_FLAG: u32 = 2;
+const SATURATED_ZERO_FLAG: u32 = 4;
 
-    assert_eq!(input_params_as_bytes.len(), 24);
 
-    let wg_size = ((no_groups / 4) as u32, no_groups as u32, 1);
-    return (wg_size, input_params_as_bytes);
+// rows covered by the dispatched workgroups, at least as many as rows to compute
+pub fn dispatched_rows(params: &crate::parameters::Parameters) -> usize {
+    let workgroup_height = params.workgroup_size[1] as usize;
+    params.rows.len().div_ceil(workgroup_height) * workgroup_height
 }
 
 
-pub fn get_spirv_configuration_u8(params: &crate::parameters::Parameters)
--> ((u32, u32, u32), Vec<u8>) {
-    const WORKGROUP_SIZE: u16 = 16;
+// workgroups covering the computed rows, an invocation computes pixels_per_invocation consecutive pixels of a row
+fn get_dispatch_size(params: &crate::parameters::Parameters) -> (u32, u32, u32) {
+    let [workgroup_width, workgroup_height] = params.workgroup_size.map(|size| size as usize);
+    let row_invocations = params.img_size_px as usize / params.pixels_per_invocation as usize;
     // processing must be divided into full workgroups
-    assert_eq!(params.img_size_px % WORKGROUP_SIZE, 0);
-    let no_groups = params.img_size_px / WORKGROUP_SIZE;
+    assert_eq!(params.img_size_px % params.pixels_per_invocation, 0);
+    assert_eq!(row_invocations % workgroup_width, 0);
+    ((row_invocations / workgroup_width) as u32, (dispatched_rows(params) / workgroup_height) as u32, 1)
+}
 
-    #[repr(C)]
-    struct InputParameters {
-        draw_bounds: [f32; 4], // -x, x, -y, y
-        img_size_px: u32,      // in pixels
-        max_iter: u32,         // max number of iterations to run
-    }
+
+fn flags(params: &crate::parameters::Parameters) -> u32 {
+    (if params.interior_checks.bulbs { BULB_CHECK_FLAG } else { 0 }) |
+        (if params.interior_checks.periodicity { PERIODICITY_CHECK_FLAG } else { 0 }) |
+        (if params.marks_saturated() { SATURATED_ZERO_FLAG } else { 0 })
+}
+
+
+// uniform of the mandelbrot shaders, the SPIR-V ones are generated from the WGSL one and share it
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct InputParameters {
+    draw_bounds: [f32; 4], // -x, x, -y, y
+    max_iter: u32,         // number of iterations mapped to the brightest value
+    img_size_px: u32,      // in pixels
+    flags: u32,            // interior checks to run, value 0 left for saturated pixels
+    budget: u32,           // max number of iterations to run
+    sample_offsets: [[f32; 2]; MAX_SAMPLES]
+}
+
+
+// contents of the uniform buffer, the fragment backend has no workgroups to dispatch
+pub fn get_uniform_data(params: &crate::parameters::Parameters) -> Vec<u8> {
+    let mut sample_offsets = [[0.0; 2]; MAX_SAMPLES];
+    sample_offsets[..params.sampling.offsets.len()].copy_from_slice(&params.sampling.offsets);
 
     let input_parameters = InputParameters {
-        draw_bounds: params.limits,
+        draw_bounds: params.band_limits().map(|v| v as f32),
+        max_iter: params.max_iter,
         img_size_px: params.img_size_px as u32,
-        max_iter: params.max_iter as u32
+        flags: flags(params),
+        budget: params.budget(),
+        sample_offsets
     };
 
-    let input_params_as_bytes = unsafe { std::slice::from_raw_parts(
-        (&input_parameters as *const InputParameters) as *const u8,
-        std::mem::size_of::<InputParameters>()
-    )}.to_vec();
+    bytemuck::bytes_of(&input_parameters).to_vec()
+}
 
-    assert_eq!(input_params_as_bytes.len(), 24);
 
-    let wg_size = (no_groups as u32, no_groups as u32, 1);
-    return (wg_size, input_params_as_bytes);
+// workgroups to dispatch and the contents of the uniform buffer, which is all that changes between renders
+pub fn get_configuration(params: &crate::parameters::Parameters) -> ((u32, u32, u32), Vec<u8>) {
+    (get_dispatch_size(params), get_uniform_data(params))
 }
 
 
-fn build_wgsl(
+pub fn get_wgpu_shader_module(
     params: &crate::parameters::Parameters,
     device: &wgpu::Device
-) -> ((u32, u32, u32), wgpu::ShaderModule, Vec<u8>)
+) -> Result<wgpu::ShaderModule, String>
 {
-    const WORKGROUP_SIZE: u16 = 16;
-    // processing must be divided into full workgroups
-    assert_eq!(params.img_size_px % WORKGROUP_SIZE, 0);
-    let no_groups = params.img_size_px / WORKGROUP_SIZE;
-    // one shader invocation processes 4 consecutive pixels (due to stupid wgsl limitation of working only on u32)
-    assert_eq!(no_groups % 4, 0);
+    let source = match params.backend_type {
+        BackendType::WgpuWgsl => wgpu::ShaderSource::Wgsl(std::borrow::Cow::from(get_mandelbrot_wgsl(params)?)),
+        BackendType::WgpuSpirv => {
+            // wgpu takes no specialization constants, their values and the workgroup size are written into the module
+            let [width, height] = params.workgroup_size;
+            let mut module = get_mandelbrot_spirv(params);
+            crate::shaders::spirv::set_local_size(&mut module, [width as u32, height as u32, 1]);
+            crate::shaders::spirv::set_spec_constants(&mut module, &get_spec_constants(params));
+            wgpu::ShaderSource::SpirV(std::borrow::Cow::from(module))
+        }
+        _ => panic!("Invalid backend for WGPU")
+    };
+    Ok(device.create_shader_module(wgpu::ShaderModuleDescriptor { label: None, source }))
+}
 
-    #[repr(C)]
-    struct InputParameters {
-        draw_bounds: [f32; 4], // -x, x, -y, y
-        max_iter: u32,         // max number of iterations to ru
-        img_size_px: u32       // in pixels
-    }
 
-    let input_parameters = InputParameters {
-        draw_bounds: params.limits,
-        max_iter: params.max_iter as u32,
-        img_size_px: params.img_size_px as u32
+// one invocation colorizes 4 pixels, a row of workgroups covers a row of the image
+pub fn get_colorize_dispatch(params: &crate::parameters::Parameters) -> (u32, u32, u32) {
+    assert_eq!(params.img_size_px % (4 * COLORIZE_WORKGROUP_SIZE), 0);
+    ((params.img_size_px / 4 / COLORIZE_WORKGROUP_SIZE) as u32, params.rows.len() as u32, 1)
+}
+
+
+pub fn get_colorize_wgpu_shader_module(device: &wgpu::Device) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::from(include_str!("colorize.wgsl"))),
+    })
+}
+
+
+// validated with the capabilities the shaders given with --shader are loaded with
+fn parse_wgsl(source: &str) -> Result<(naga::Module, naga::valid::ModuleInfo), String> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|error| error.emit_to_string(source))?;
+    let info = crate::shaders::validation::validate(&module).map_err(|error| crate::shaders::loader::error_chain(&error))?;
+    Ok((module, info))
+}
+
+
+// GLSL source of an entry point of the WGSL shader with the names of its uniform blocks, resources keep the binding
+// numbers they have in WGSL where the version allows explicit bindings
+pub fn wgsl_to_glsl(
+    source: &str,
+    version: naga::back::glsl::Version,
+    shader_stage: naga::ShaderStage,
+    entry_point: &str
+) -> Result<(String, Vec<String>), String> {
+    let (module, info) = parse_wgsl(source)?;
+    let binding_map = module.global_variables.iter()
+        .filter_map(|(_, global)| global.binding.clone())
+        .map(|binding| {
+            let index = binding.binding as u8;
+            (binding, index)
+        })
+        .collect();
+    let options = naga::back::glsl::Options {
+        version,
+        writer_flags: naga::back::glsl::WriterFlags::empty(),
+        binding_map
+    };
+    let pipeline_options = naga::back::glsl::PipelineOptions {
+        shader_stage,
+        entry_point: entry_point.to_string(),
+        multiview: None
     };
 
-    let input_params_as_bytes = unsafe { std::slice::from_raw_parts(
-        (&input_parameters as *const InputParameters) as *const u8,
-        std::mem::size_of::<InputParameters>()
-    )}.to_vec();
+    let mut glsl = String::new();
+    let reflection = naga::back::glsl::Writer::new(&mut glsl, &module, &info, &options, &pipeline_options, Default::default())
+        .and_then(|mut writer| writer.write())
+        .map_err(|error| format!("Cannot translate {entry_point} to GLSL {version:?}: {error}"))?;
+    let uniform_blocks: Vec<String> = reflection.uniforms.into_values().collect();
+    // blocks are declared without a layout where the version has no explicit bindings, which leaves the offsets of
+    // their members to the driver, the uniform buffer is written in std140
+    for block in &uniform_blocks {
+        glsl = glsl.replace(&format!("\nuniform {block} {{"), &format!("\nlayout(std140) uniform {block} {{"));
+    }
+    Ok((glsl, uniform_blocks))
+}
 
-    assert_eq!(input_params_as_bytes.len(), 24);
 
-    let wg_size = ((no_groups / 4) as u32, no_groups as u32, 1);
-    let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: None,
-        source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::from(include_str!("mandelbrot.wgsl"))),
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+
+// source with the line and (nested) block comments blanked out, byte offsets stay the same
+fn without_comments(source: &str) -> String {
+    let bytes = source.as_bytes();
+    let mut code = bytes.to_vec();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let (comment, len) = match &bytes[i..bytes.len().min(i + 2)] {
+            b"//" if depth == 0 => (true, bytes[i..].iter().position(|b| *b == b'\n').unwrap_or(bytes.len() - i)),
+            b"/*" => {
+                depth += 1;
+                (true, 2)
+            }
+            b"*/" if depth > 0 => {
+                depth -= 1;
+                (true, 2)
+            }
+            _ => (depth > 0, 1)
+        };
+        if comment {
+            code[i..i + len].iter_mut().filter(|b| **b != b'\n').for_each(|b| *b = b' ');
+        }
+        i += len;
+    }
+    String::from_utf8(code).unwrap()
+}
+
+
+// byte range of the source and the text replacing it
+type Replacement = (std::ops::Range<usize>, String);
+
+
+// arguments of every occurrence of the attribute, which may span lines
+fn attribute_replacements(code: &str, attribute: &str, arguments: &str) -> Result<Vec<Replacement>, String> {
+    let mut replacements = Vec::new();
+    for (start, _) in code.match_indices(attribute) {
+        let after = &code[start + attribute.len()..];
+        if after.starts_with(is_identifier_char) {
+            continue;
+        }
+        if !after.trim_start().starts_with('(') {
+            return Err(format!("{attribute} without arguments"));
+        }
+        let close = after.find(')').ok_or(format!("Unterminated {attribute} attribute"))?;
+        replacements.push((start + attribute.len()..start + attribute.len() + close + 1, format!("({arguments})")));
+    }
+    if replacements.is_empty() {
+        return Err(format!("Shader has no {attribute} attribute"));
+    }
+    Ok(replacements)
+}
+
+
+// declaration of a module scope u32 constant, typed or not
+fn constant_replacement(code: &str, name: &str, value: usize) -> Result<Replacement, String> {
+    let declaration = code.match_indices("let").find_map(|(start, _)| {
+        let module_scope = code[..start].matches('{').count() == code[..start].matches('}').count();
+        if !module_scope || code[..start].ends_with(is_identifier_char) {
+            return None;
+        }
+        let after = &code["let".len() + start..];
+        let after_name = after.trim_start().strip_prefix(name)?;
+        if after.starts_with(is_identifier_char) || after_name.starts_with(is_identifier_char) {
+            return None;
+        }
+        let end = code.len() - after_name.len() + after_name.find(';')? + 1;
+        Some((start..end, after_name.split('=').next().unwrap().trim()))
     });
+    let (range, declared_type) = declaration.ok_or(format!("Shader has no {name} constant"))?;
+    match declared_type.strip_prefix(':').map(str::trim) {
+        None if declared_type.is_empty() => (),
+        Some("u32") => (),
+        _ => return Err(format!("Constant {name} is not declared as u32"))
+    }
+    Ok((range, format!("let {name}: u32 = {value}u;")))
+}
+
+
+// WGSL has no specialization constants, the workgroup size and the module constants the parameters set are written
+// into the source in place of the values it declares, comments mentioning them are left as they are
+fn template_wgsl(source: &str, workgroup_size: [u16; 2], pixels_per_invocation: u16, sample_count: usize) -> Result<String, String> {
+    let [width, height] = workgroup_size;
+    let code = without_comments(source);
+    let mut replacements = attribute_replacements(&code, "@workgroup_size", &format!("{width}, {height}"))?;
+    replacements.push(constant_replacement(&code, "PIXELS_PER_INVOCATION", pixels_per_invocation as usize)?);
+    replacements.push(constant_replacement(&code, "SAMPLE_COUNT", sample_count)?);
 
-    return (wg_size, shader_module, input_params_as_bytes);
+    replacements.sort_by_key(|(range, _)| range.start);
+    let mut templated = source.to_string();
+    for (range, text) in replacements.into_iter().rev() {
+        templated.replace_range(range, &text);
+    }
+    Ok(templated)
 }
 
 
-fn build_spirv(
-    params: &crate::parameters::Parameters,
-    device: &wgpu::Device
-) -> ((u32, u32, u32), wgpu::ShaderModule, Vec<u8>)
-{
-    let (wg_size, input_params_as_bytes) = get_spirv_configuration(&params);
-    let shader_module = device.create_shader_module(wgpu::include_spirv!("mandelbrot-u32.rs.spv"));
-    return (wg_size, shader_module, input_params_as_bytes);
+// shaders given with --shader have to declare the values templated into them
+pub fn check_template(source: &str) -> Result<(), String> {
+    template_wgsl(source, [16, 16], 4, 16).map(|_| ())
 }
 
 
-pub fn get_wgpu_shader(
-    params: &crate::parameters::Parameters,
-    device: &wgpu::Device
-) -> ((u32, u32, u32), wgpu::ShaderModule, Vec<u8>)
-{
-    match params.backend_type {
-        BackendType::WgpuWgsl => build_wgsl(params, device),
-        BackendType::WgpuSpirv => build_spirv(params, device),
-        _ => panic!("Invalid backend for WGPU")
+// WGSL source of the mandelbrot shader, the one given with --shader replaces the built-in one
+fn get_mandelbrot_wgsl(params: &crate::parameters::Parameters) -> Result<String, String> {
+    let source = match params.shader.as_deref() {
+        Some(Shader::Wgsl(source)) => source,
+        Some(Shader::Spirv(_)) => panic!("SPIR-V shader given to a backend running WGSL"),
+        None => include_str!("mandelbrot.wgsl")
+    };
+    template_wgsl(source, params.workgroup_size, params.pixels_per_invocation, params.sampling.offsets.len())
+}
+
+
+// mandelbrot shader for OpenGL drivers without GL_ARB_gl_spirv, WGSL is the source of both APIs
+pub fn get_mandelbrot_glsl(params: &crate::parameters::Parameters) -> Result<String, String> {
+    Ok(wgsl_to_glsl(&get_mandelbrot_wgsl(params)?, GLSL_COMPUTE_VERSION, naga::ShaderStage::Compute, "main")?.0)
+}
+
+
+pub fn get_colorize_glsl() -> String {
+    wgsl_to_glsl(include_str!("colorize.wgsl"), GLSL_COMPUTE_VERSION, naga::ShaderStage::Compute, "main")
+        .expect("Built-in colorization shader does not translate to GLSL").0
+}
+
+
+// GLSL 3.30 of the OpenGL fragment backend or ESSL 3.00 of the OpenGL ES one
+pub fn fragment_glsl_version(es: bool) -> naga::back::glsl::Version {
+    if es {
+        naga::back::glsl::Version::Embedded { version: 300, is_webgl: false }
+    } else {
+        naga::back::glsl::Version::Desktop(330)
+    }
+}
+
+
+// vertex and fragment shaders for GL 3.3 or GLES 3.0, which have no explicit bindings, with the name of the uniform
+// block holding the input parameters
+pub fn get_mandelbrot_fragment_glsl(params: &crate::parameters::Parameters, es: bool) -> Result<(String, String, String), String> {
+    let version = fragment_glsl_version(es);
+    let source = get_mandelbrot_wgsl(params)?;
+    let (vertex, _) = wgsl_to_glsl(&source, version, naga::ShaderStage::Vertex, "vs_main")?;
+    let (fragment, uniform_blocks) = wgsl_to_glsl(&source, version, naga::ShaderStage::Fragment, "fs_main")?;
+    let uniform_block = uniform_blocks.into_iter().next().ok_or("Fragment shader has no uniform block")?;
+    Ok((vertex, fragment, uniform_block))
+}
+
+
+// SPIR-V of the mandelbrot shader, the one given with --shader replaces the built-in ones build.rs generates from the
+// WGSL shader
+pub fn get_mandelbrot_spirv(params: &crate::parameters::Parameters) -> Vec<u32> {
+    match (params.shader.as_deref(), params.backend_type) {
+        (Some(Shader::Spirv(module)), _) => module.clone(),
+        (Some(Shader::Wgsl(_)), _) => panic!("WGSL shader given to a backend running SPIR-V"),
+        (None, BackendType::OpenglSpirv | BackendType::WgpuSpirv) =>
+            wgpu::util::make_spirv_raw(include_bytes!(concat!(env!("OUT_DIR"), "/mandelbrot.spv"))).into_owned(),
+        (None, BackendType::OpenglSpirvU8) =>
+            wgpu::util::make_spirv_raw(include_bytes!(concat!(env!("OUT_DIR"), "/mandelbrot-u8.spv"))).into_owned(),
+        _ => panic!("Invalid backend for SPIR-V")
+    }
+}
+
+
+// values of the specialization constants set by the parameters, modules take those they declare
+pub fn get_spec_constants(params: &crate::parameters::Parameters) -> Vec<(u32, u32)> {
+    let [width, height] = params.workgroup_size;
+    let [width_id, height_id, depth_id] = crate::shaders::spirv::WORKGROUP_SIZE_IDS;
+    vec![
+        (width_id, width as u32),
+        (height_id, height as u32),
+        (depth_id, 1),
+        (crate::shaders::spirv::PIXELS_PER_INVOCATION_ID, params.pixels_per_invocation as u32),
+        (crate::shaders::spirv::SAMPLE_COUNT_ID, params.sampling.offsets.len() as u32)
+    ]
+}
+
+
+// colorization shader translated to SPIR-V for OpenGL, the WGSL source is shared with wgpu
+pub fn get_colorize_spirv() -> Vec<u32> {
+    let (module, info) = parse_wgsl(include_str!("colorize.wgsl")).expect("Built-in colorization shader is invalid");
+    naga::back::spv::write_vec(&module, &info, &naga::back::spv::Options::default(), None).unwrap()
+}
+
+
+// members of the uniform with their offsets, as the shaders have to declare them
+pub fn input_parameters_layout() -> Vec<(String, u32)> {
+    [
+        ("draw_bounds", std::mem::offset_of!(InputParameters, draw_bounds)),
+        ("max_iter", std::mem::offset_of!(InputParameters, max_iter)),
+        ("img_size_px", std::mem::offset_of!(InputParameters, img_size_px)),
+        ("flags", std::mem::offset_of!(InputParameters, flags)),
+        ("budget", std::mem::offset_of!(InputParameters, budget)),
+        ("sample_offsets", std::mem::offset_of!(InputParameters, sample_offsets))
+    ].map(|(name, offset)| (name.to_string(), offset as u32)).to_vec()
+}
+
+
+pub fn input_parameters_size() -> usize {
+    std::mem::size_of::<InputParameters>()
+}
+
+
+// members and size of the uniform block the shader reads
+fn uniform_layout(module: &naga::Module, ty: naga::Handle<naga::Type>) -> Result<(Vec<(String, u32)>, u32), String> {
+    let mut ty = ty;
+    loop {
+        match &module.types[ty].inner {
+            // naga wraps the uniform structs of the SPIR-V it writes in a block struct
+            naga::TypeInner::Struct { members, .. } if members.len() == 1 &&
+                matches!(module.types[members[0].ty].inner, naga::TypeInner::Struct { .. }) => ty = members[0].ty,
+            naga::TypeInner::Struct { members, span } => return Ok((
+                members.iter().map(|member| (member.name.clone().unwrap_or_default(), member.offset)).collect(),
+                *span
+            )),
+            inner => return Err(format!("Uniform is not a struct: {inner:?}"))
+        }
+    }
+}
+
+
+// the uniform buffer is bound at binding 0 and the pixels at binding 1 of group 0, the members of the uniform have to
+// be at the offsets of InputParameters, where the buffer has them
+pub fn check_interface(module: &naga::Module) -> Result<(), String> {
+    let binding = |binding| Some(naga::ResourceBinding { group: 0, binding });
+    let mut uniforms = module.global_variables.iter().filter(|(_, global)| global.space == naga::AddressSpace::Uniform);
+    let (_, uniform) = uniforms.next().ok_or("Shader declares no uniform")?;
+    if uniforms.next().is_some() {
+        return Err("Shader declares more than one uniform".to_string());
+    }
+    if uniform.binding != binding(0) {
+        return Err(format!("Uniform is bound at {:?}, expected group 0, binding 0", uniform.binding));
+    }
+    if let Some((_, storage)) = module.global_variables.iter()
+        .find(|(_, global)| matches!(global.space, naga::AddressSpace::Storage { .. }) && global.binding != binding(1)) {
+        return Err(format!("Storage buffer is bound at {:?}, expected group 0, binding 1", storage.binding));
+    }
+
+    let (members, size) = uniform_layout(module, uniform.ty)?;
+    if members != input_parameters_layout() || size as usize != std::mem::size_of::<InputParameters>() {
+        return Err(format!(
+            "Uniform members {members:?} of {size} bytes do not match {:?} of {} bytes the uniform buffer holds",
+            input_parameters_layout(), std::mem::size_of::<InputParameters>()
+        ));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    fn assert_uniform_layout(module: &naga::Module) {
+        super::check_interface(module).unwrap();
+    }
+
+
+    #[test]
+    fn wgsl_uniform_layout() {
+        assert_uniform_layout(&naga::front::wgsl::parse_str(include_str!("mandelbrot.wgsl")).unwrap());
+    }
+
+
+    // naga does not know the extension of 8-bit storage the Int8 variant declares, it does not change the uniform
+    fn without_extensions(bytes: &[u8]) -> Vec<u32> {
+        let words: Vec<u32> = bytemuck::pod_collect_to_vec(bytes);
+        let instructions = crate::shaders::spirv::instructions(&words)
+            .filter(|i| crate::shaders::spirv::opcode(i) != spirv::Op::Extension as u32);
+        words[..5].iter().copied().chain(instructions.flatten().copied()).collect()
+    }
+
+
+    #[test]
+    fn spirv_uniform_layout() {
+        let modules: [&[u8]; 2] = [
+            include_bytes!(concat!(env!("OUT_DIR"), "/mandelbrot.spv")),
+            include_bytes!(concat!(env!("OUT_DIR"), "/mandelbrot-u8.spv"))
+        ];
+        for module in modules {
+            let module = naga::front::spv::Parser::new(without_extensions(module).into_iter(), &Default::default())
+                .parse()
+                .unwrap();
+            assert_uniform_layout(&module);
+        }
+    }
+
+
+    // blocks declared without a layout could be laid out differently than the uniform buffer by the driver
+    #[test]
+    fn fragment_uniform_blocks_are_std140() {
+        let params = crate::parameters::parse_params(&["opengl-fragment", "64", "100", "--", "-2.2", "0.8", "-1.5", "1.5"]).unwrap();
+        for es in [false, true] {
+            let (_, fragment, block) = super::get_mandelbrot_fragment_glsl(&params, es).unwrap();
+            assert!(fragment.contains(&format!("\nlayout(std140) uniform {block} {{")), "{fragment}");
+            assert!(!fragment.contains("\nuniform "), "{fragment}");
+        }
+    }
+
+
+    #[test]
+    fn templates_built_in_shader() {
+        let source = super::template_wgsl(include_str!("mandelbrot.wgsl"), [8, 4], 12, 64).unwrap();
+        assert_eq!(source.matches("@workgroup_size(8, 4)").count(), 2);
+        assert!(source.contains("let PIXELS_PER_INVOCATION: u32 = 12u;"));
+        assert!(source.contains("let SAMPLE_COUNT: u32 = 64u;"));
+        naga::front::wgsl::parse_str(&source).unwrap();
+    }
+
+
+    #[test]
+    fn templates_split_attributes_and_untyped_constants() {
+        let source = include_str!("mandelbrot.wgsl")
+            .replace("@workgroup_size(16, 16)", "@workgroup_size(\n    16,\n    16\n)")
+            .replace("let PIXELS_PER_INVOCATION: u32 = 4u;", "let PIXELS_PER_INVOCATION = 4u;");
+        let templated = super::template_wgsl(&source, [8, 4], 12, 64).unwrap();
+        assert_eq!(templated.matches("@workgroup_size(8, 4)").count(), 2);
+        assert!(templated.contains("let PIXELS_PER_INVOCATION: u32 = 12u;"));
+    }
+
+
+    #[test]
+    fn reports_missing_template_values() {
+        let source = include_str!("mandelbrot.wgsl");
+        let errors = [
+            source.replace("@workgroup_size", "@size"),
+            source.replace("let SAMPLE_COUNT", "let SAMPLES"),
+            source.replace("let SAMPLE_COUNT: u32 = 16u;", "let SAMPLE_COUNT: i32 = 16;")
+        ].map(|source| super::check_template(&source).unwrap_err());
+        assert!(errors[0].contains("@workgroup_size"), "{}", errors[0]);
+        assert!(errors[1].contains("SAMPLE_COUNT"), "{}", errors[1]);
+        assert!(errors[2].contains("u32"), "{}", errors[2]);
+    }
+
+
+    #[test]
+    fn templates_declarations_after_comments_mentioning_them() {
+        let source = include_str!("mandelbrot.wgsl").replacen(
+            "let PIXELS_PER_INVOCATION",
+            "// let PIXELS_PER_INVOCATION: u32 = 2u; with @workgroup_size(1)\n/* let SAMPLE_COUNT = 1u; /* nested */ */\nlet PIXELS_PER_INVOCATION",
+            1
+        );
+        let templated = super::template_wgsl(&source, [8, 4], 12, 64).unwrap();
+        assert!(templated.contains("// let PIXELS_PER_INVOCATION: u32 = 2u; with @workgroup_size(1)\n"));
+        assert!(templated.contains("/* let SAMPLE_COUNT = 1u; /* nested */ */\nlet PIXELS_PER_INVOCATION: u32 = 12u;"));
+        assert!(templated.contains("let SAMPLE_COUNT: u32 = 64u;"));
+        assert_eq!(templated.matches("@workgroup_size(8, 4)").count(), 2);
+        naga::front::wgsl::parse_str(&templated).unwrap();
     }
 }
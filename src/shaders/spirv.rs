@@ -0,0 +1,256 @@
+// in-place edits of SPIR-V modules, which take the workgroup size and the other values the parameters set as
+// specialization constants, the built-in shaders declare the IDs build.rs gives their template constants
+// build.rs includes this file as well, to walk and assemble the instructions of the modules it generates
+
+const HEADER_WORDS: usize = 5;
+const BOUND_WORD: usize = 3;
+
+// specialization constant IDs of the values set by the parameters, IDs 0 to 2 make up the WorkgroupSize built-in like
+// local_size_x_id and friends of GLSL
+pub const WORKGROUP_SIZE_IDS: [u32; 3] = [0, 1, 2];
+pub const PIXELS_PER_INVOCATION_ID: u32 = 3;
+pub const SAMPLE_COUNT_ID: u32 = 4;
+
+
+pub fn opcode(instruction: &[u32]) -> u32 {
+    instruction[0] & 0xffff
+}
+
+
+// offsets of the instructions following the header, the word count is in the upper half of the first word
+fn instruction_offsets(words: &[u32]) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    let mut offset = HEADER_WORDS;
+    while offset < words.len() {
+        offsets.push(offset);
+        offset += (words[offset] >> 16) as usize;
+    }
+    offsets
+}
+
+
+pub fn instructions(words: &[u32]) -> impl Iterator<Item = &[u32]> {
+    instruction_offsets(words).into_iter().map(|offset| &words[offset..offset + (words[offset] >> 16) as usize])
+}
+
+
+pub fn instruction(op: spirv::Op, operands: &[u32]) -> Vec<u32> {
+    let mut words = vec![(operands.len() as u32 + 1) << 16 | op as u32];
+    words.extend_from_slice(operands);
+    words
+}
+
+
+// result IDs of the specialization constants by their SpecId decoration
+fn spec_constants(words: &[u32]) -> Vec<(u32, u32)> {
+    instructions(words)
+        .filter(|i| opcode(i) == spirv::Op::Decorate as u32 && i[2] == spirv::Decoration::SpecId as u32)
+        .map(|i| (i[3], i[1]))
+        .collect()
+}
+
+
+pub fn spec_constant_ids(words: &[u32]) -> Vec<u32> {
+    spec_constants(words).into_iter().map(|(spec_id, _)| spec_id).collect()
+}
+
+
+fn is_workgroup_size(i: &[u32]) -> bool {
+    opcode(i) == spirv::Op::Decorate as u32 &&
+        i[2] == spirv::Decoration::BuiltIn as u32 && i[3] == spirv::BuiltIn::WorkgroupSize as u32
+}
+
+
+// sets the workgroup size of the LocalSize execution modes, for APIs which cannot specialize the module, a WorkgroupSize
+// built-in overrides them and becomes a composite of new constants, its own may be shared with other expressions
+pub fn set_local_size(words: &mut Vec<u32>, size: [u32; 3]) {
+    for offset in instruction_offsets(words) {
+        let i = &mut words[offset..];
+        if opcode(i) == spirv::Op::ExecutionMode as u32 && i[2] == spirv::ExecutionMode::LocalSize as u32 {
+            i[3..6].copy_from_slice(&size);
+        }
+    }
+
+    let Some(workgroup_size) = instructions(words).find(|i| is_workgroup_size(i)).map(|i| i[1])
+    else { return; };
+    let is_composite = |i: &[u32]| [spirv::Op::ConstantComposite as u32, spirv::Op::SpecConstantComposite as u32].contains(&opcode(i));
+    let composite_at = instruction_offsets(words).into_iter()
+        .find(|offset| is_composite(&words[*offset..]) && words[offset + 2] == workgroup_size)
+        .expect("WorkgroupSize built-in is not a constant composite");
+    let component = words[composite_at + 3];
+    let uint = instructions(words)
+        .find(|i| [spirv::Op::Constant as u32, spirv::Op::SpecConstant as u32].contains(&opcode(i)) && i[2] == component)
+        .map(|i| i[1])
+        .expect("WorkgroupSize built-in component is not a scalar constant");
+
+    let mut constants = Vec::new();
+    for (k, value) in size.into_iter().enumerate() {
+        let id = words[BOUND_WORD];
+        words[BOUND_WORD] += 1;
+        constants.extend(instruction(spirv::Op::Constant, &[uint, id, value]));
+        words[composite_at + 3 + k] = id;
+    }
+    words[composite_at] = words[composite_at] & !0xffff | spirv::Op::ConstantComposite as u32;
+    words.splice(composite_at..composite_at, constants);
+}
+
+
+// replaces the default values of 32-bit scalar specialization constants, for APIs which cannot specialize the module
+pub fn set_spec_constants(words: &mut [u32], values: &[(u32, u32)]) {
+    let ids = spec_constants(words);
+    for offset in instruction_offsets(words) {
+        let i = &mut words[offset..];
+        if opcode(i) != spirv::Op::SpecConstant as u32 || i[0] >> 16 != 4
+        { continue; }
+        let spec_id = ids.iter().find(|(_, id)| *id == i[2]).map(|(spec_id, _)| *spec_id);
+        if let Some(&(_, value)) = values.iter().find(|(id, _)| Some(*id) == spec_id) {
+            i[3] = value;
+        }
+    }
+}
+
+
+// declares the WorkgroupSize built-in as a composite of specialization constants WORKGROUP_SIZE_IDS, defaulting to the
+// LocalSize execution mode, unless the module already declares it
+pub fn add_workgroup_size_spec_constants(words: &[u32]) -> Vec<u32> {
+    if instructions(words).any(is_workgroup_size) {
+        return words.to_vec();
+    }
+    let local_size = instructions(words)
+        .find(|i| opcode(i) == spirv::Op::ExecutionMode as u32 && i[2] == spirv::ExecutionMode::LocalSize as u32)
+        .map(|i| [i[3], i[4], i[5]])
+        .expect("Compute shader without LocalSize execution mode");
+
+    let mut bound = words[BOUND_WORD];
+    let mut new_id = || {
+        bound += 1;
+        bound - 1
+    };
+    let mut types = Vec::new();
+    let uint = match instructions(words).find(|i| opcode(i) == spirv::Op::TypeInt as u32 && i[2..] == [32, 0]) {
+        Some(i) => i[1],
+        None => {
+            let id = new_id();
+            types.extend(instruction(spirv::Op::TypeInt, &[id, 32, 0]));
+            id
+        }
+    };
+    let uvec3 = match instructions(words).find(|i| opcode(i) == spirv::Op::TypeVector as u32 && i[2..] == [uint, 3]) {
+        Some(i) => i[1],
+        None => {
+            let id = new_id();
+            types.extend(instruction(spirv::Op::TypeVector, &[id, uint, 3]));
+            id
+        }
+    };
+
+    let mut decorations = Vec::new();
+    let mut constants = types;
+    let mut components = Vec::new();
+    for (spec_id, size) in WORKGROUP_SIZE_IDS.into_iter().zip(local_size) {
+        let id = new_id();
+        decorations.extend(instruction(spirv::Op::Decorate, &[id, spirv::Decoration::SpecId as u32, spec_id]));
+        constants.extend(instruction(spirv::Op::SpecConstant, &[uint, id, size]));
+        components.push(id);
+    }
+    let workgroup_size = new_id();
+    decorations.extend(instruction(spirv::Op::Decorate,
+        &[workgroup_size, spirv::Decoration::BuiltIn as u32, spirv::BuiltIn::WorkgroupSize as u32]));
+    constants.extend(instruction(spirv::Op::SpecConstantComposite, &[&[uvec3, workgroup_size][..], &components].concat()));
+
+    // decorations go after the existing annotations, constants after the existing types, constants and globals
+    let offsets = instruction_offsets(words);
+    let is_annotation = |offset: &usize| (spirv::Op::Decorate as u32..=spirv::Op::GroupMemberDecorate as u32)
+        .contains(&opcode(&words[*offset..]));
+    let decorations_at = offsets.iter().rev().find(|o| is_annotation(o))
+        .map(|o| o + (words[*o] >> 16) as usize)
+        .unwrap();
+    let constants_at = offsets.into_iter().find(|o| opcode(&words[*o..]) == spirv::Op::Function as u32).unwrap();
+
+    let mut patched = Vec::with_capacity(words.len() + decorations.len() + constants.len());
+    patched.extend_from_slice(&words[..decorations_at]);
+    patched.extend(decorations);
+    patched.extend_from_slice(&words[decorations_at..constants_at]);
+    patched.extend(constants);
+    patched.extend_from_slice(&words[constants_at..]);
+    patched[BOUND_WORD] = bound;
+    patched
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn built_in() -> Vec<u32> {
+        bytemuck::pod_collect_to_vec(include_bytes!(concat!(env!("OUT_DIR"), "/mandelbrot.spv")))
+    }
+
+
+    fn parse(words: &[u32]) -> naga::Module {
+        naga::front::spv::Parser::new(words.iter().copied(), &Default::default()).parse().unwrap()
+    }
+
+
+    fn spec_constant_value(words: &[u32], spec_id: u32) -> u32 {
+        let (_, id) = spec_constants(words).into_iter().find(|(s, _)| *s == spec_id).unwrap();
+        instructions(words).find(|i| opcode(i) == spirv::Op::SpecConstant as u32 && i[2] == id).unwrap()[3]
+    }
+
+
+    #[test]
+    fn sets_local_size() {
+        let mut words = built_in();
+        set_local_size(&mut words, [8, 4, 1]);
+        assert_eq!(parse(&words).entry_points[0].workgroup_size, [8, 4, 1]);
+    }
+
+
+    #[test]
+    fn sets_spec_constants() {
+        let mut words = built_in();
+        set_spec_constants(&mut words, &[(PIXELS_PER_INVOCATION_ID, 8), (SAMPLE_COUNT_ID, 4)]);
+        let module = parse(&words);
+        for (spec_id, value) in [(PIXELS_PER_INVOCATION_ID, 8), (SAMPLE_COUNT_ID, 4)] {
+            let (_, constant) = module.constants.iter().find(|(_, c)| c.specialization == Some(spec_id)).unwrap();
+            assert!(matches!(constant.inner,
+                naga::ConstantInner::Scalar { value: naga::ScalarValue::Uint(v), .. } if v == value));
+        }
+    }
+
+
+    // naga does not parse OpSpecConstantComposite, the composite is checked by walking the module
+    #[test]
+    fn adds_workgroup_size_spec_constants() {
+        let words = add_workgroup_size_spec_constants(&built_in());
+        assert!(instructions(&words).any(is_workgroup_size));
+        assert_eq!(words[BOUND_WORD], built_in()[BOUND_WORD] + 4);
+        let local_size = parse(&built_in()).entry_points[0].workgroup_size;
+        for (spec_id, size) in WORKGROUP_SIZE_IDS.into_iter().zip(local_size) {
+            assert_eq!(spec_constant_value(&words, spec_id), size);
+        }
+        assert_eq!(add_workgroup_size_spec_constants(&words), words);
+    }
+
+
+    #[test]
+    fn sets_local_size_of_workgroup_size_built_in() {
+        let mut words = add_workgroup_size_spec_constants(&built_in());
+        set_local_size(&mut words, [8, 4, 1]);
+        let module = parse(&words);
+        assert_eq!(module.entry_points[0].workgroup_size, [8, 4, 1]);
+        let sizes: Vec<Vec<u64>> = module.constants.iter()
+            .filter_map(|(_, c)| match &c.inner {
+                naga::ConstantInner::Composite { components, .. } => Some(components.iter()
+                    .filter_map(|h| match module.constants[*h].inner {
+                        naga::ConstantInner::Scalar { value: naga::ScalarValue::Uint(v), .. } => Some(v),
+                        _ => None
+                    })
+                    .collect()),
+                _ => None
+            })
+            .collect();
+        assert!(sizes.contains(&vec![8, 4, 1]));
+    }
+}
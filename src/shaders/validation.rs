@@ -0,0 +1,9 @@
+// build.rs includes this file as well, the built-in shaders and the ones given with --shader are validated alike
+
+// none of the optional capabilities, e.g. f64 or push constants, which not every backend or driver supports
+pub const CAPABILITIES: naga::valid::Capabilities = naga::valid::Capabilities::empty();
+
+
+pub fn validate(module: &naga::Module) -> Result<naga::valid::ModuleInfo, naga::WithSpan<naga::valid::ValidationError>> {
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all(), CAPABILITIES).validate(module)
+}
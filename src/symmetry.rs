@@ -0,0 +1,180 @@
+use crate::backends::cpu::row_coordinate;
+use crate::parameters::{BackendType, Parameters};
+use crate::result::ComputeResult;
+
+
+// how far (in rows) the mirrored grid may be from the real one to still be considered aligned
+const ALIGNMENT_TOLERANCE: f64 = 1e-6;
+
+
+pub struct Mirror {
+    // rows which have to be computed, all the others are mirror images of them
+    pub computed: std::ops::Range<u16>,
+    // row `r` is the mirror image of row `axis2 - r`
+    pub axis2: i64
+}
+
+
+// imaginary part of the row in the arithmetic of the backend, shaders compute it in f32 from the limits of the
+// rendered band, which the compiler may turn into a fused multiply-add, so both roundings are returned
+fn row_coordinates(row: u16, params: &Parameters) -> Vec<f64> {
+    if !params.backend_type.is_gpu() {
+        return vec![row_coordinate(row, params)];
+    }
+    let [_, _, ymin, ymax] = params.band_limits().map(|v| v as f32);
+    let v = (row - params.rows.start) as f32 / params.img_size_px as f32;
+    vec![(ymin * (1.0 - v) + ymax * v) as f64, ymin.mul_add(1.0 - v, ymax * v) as f64]
+}
+
+
+pub fn find_mirror(params: &Parameters) -> Option<Mirror> {
+    let size = params.img_size_px as i64;
+    let [_, _, ymin, ymax] = params.limits;
+
+    // twice the (fractional) row at which the real axis lies
+    let axis2_f = -2.0 * ymin * size as f64 / (ymax - ymin);
+    let axis2 = axis2_f.round();
+    if !axis2.is_finite() || (axis2_f - axis2).abs() > ALIGNMENT_TOLERANCE {
+        return None;
+    }
+    let axis2 = axis2 as i64;
+    if axis2 < 1 || axis2 > 2 * size - 3 {
+        // real axis does not cross the image
+        return None;
+    }
+
+    // rows from `axis2 - size + 1` to `size - 1` have their mirror inside the image, only the side of the axis
+    // with the rows which do not have a mirror needs computing
+    let computed = if axis2 >= size - 1 {
+        0..(axis2 / 2 + 1)
+    } else {
+        ((axis2 + 1) / 2)..size
+    };
+    let mirror = Mirror { computed: computed.start as u16..computed.end as u16, axis2 };
+
+    // samples of the mirrored pixel are mirrored only if the pattern is symmetric about the horizontal axis
+    let offsets = &params.sampling.offsets;
+    if !offsets.iter().all(|[u, v]| offsets.contains(&[*u, -v])) {
+        tracing::debug!("View is symmetric about the real axis, but the sample pattern is not");
+        return None;
+    }
+
+    if params.backend_type == BackendType::Hybrid {
+        // rows are split between the CPU and the GPU arithmetic at run time, so their rounding cannot be checked
+        tracing::debug!("View is symmetric about the real axis, but the hybrid backend does not mirror rows");
+        return None;
+    }
+
+    // rounding of the row coordinates has to be symmetric as well, otherwise mirrored rows would differ from
+    // the ones of a full render
+    let band_params = Parameters { rows: mirror.computed.clone(), ..params.clone() };
+    let exact = (0..params.img_size_px).all(|r| {
+        let (source, sign) = if mirror.computed.contains(&r) { (r, 1.0) } else { ((axis2 - r as i64) as u16, -1.0) };
+        let band = row_coordinates(source, &band_params);
+        row_coordinates(r, params).iter().zip(&band).all(|(full, band)| *full == sign * band)
+    });
+    if !exact {
+        tracing::debug!("View is symmetric about the real axis, but its rows do not mirror exactly");
+        return None;
+    }
+    Some(mirror)
+}
+
+
+fn mirror_rows<P: Copy + Default>(band: &[P], mirror: &Mirror, params: &Parameters) -> Vec<P> {
+    let row_len = params.img_size_px as usize;
+    let mut data = vec![P::default(); row_len * params.rows.len()];
+    let band_start = mirror.computed.start as usize * row_len;
+    data[band_start..band_start + band.len()].copy_from_slice(band);
+    for row in params.rows.clone().filter(|r| !mirror.computed.contains(r)) {
+        let source = (mirror.axis2 - row as i64) as usize;
+        data.copy_within(source * row_len..(source + 1) * row_len, row as usize * row_len);
+    }
+    data
+}
+
+
+pub fn render(
+    params: &Parameters,
+    mut render_rows: impl FnMut(&Parameters) -> Result<ComputeResult, Box<dyn std::error::Error>>
+) -> Result<ComputeResult, Box<dyn std::error::Error>> {
+    let mirror = if params.symmetry { find_mirror(params) } else { None };
+    let mirror = match mirror {
+        Some(mirror) if mirror.computed.len() < params.rows.len() && params.rows == (0..params.img_size_px) => mirror,
+        _ => return render_rows(params)
+    };
+
+    tracing::debug!("View is symmetric about the real axis, computing rows {:?}", mirror.computed);
+    let band_params = Parameters { rows: mirror.computed.clone(), ..params.clone() };
+    let band = render_rows(&band_params)?;
+
+    let start_time = std::time::Instant::now();
+    let data = mirror_rows(&band.data, &mirror, params);
+    let colors = band.colors.as_ref().map(|colors| mirror_rows(colors, &mirror, params));
+
+    Ok(ComputeResult {
+        data,
+        colors,
+        costs: None,
+        data_fetch_time: band.data_fetch_time + start_time.elapsed(),
+        ..band
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::parameters::parse_params;
+
+    use super::{find_mirror, render};
+
+
+    fn params_for(backend: &str, options: &[&str], view: [&str; 4]) -> crate::parameters::Parameters {
+        parse_params(&[&[backend, "64", "100"][..], options, &["--"], &view].concat()).unwrap()
+    }
+
+
+    #[test]
+    fn mirrored_render_matches_full_render() {
+        for view in [["-2.2", "0.8", "-1.5", "1.5"], ["-2.2", "0.8", "-0.5", "1.5"], ["-2.2", "0.8", "-1.5", "0.5"]] {
+            let params = params_for("cpu-par-iter", &[], view);
+            let mirror = find_mirror(&params).unwrap();
+            assert!(mirror.computed.len() < params.rows.len());
+
+            let full = crate::backends::cpu::run_cpu_par_iter(&params);
+            let mirrored = render(&params, |params| Ok(crate::backends::cpu::run_cpu_par_iter(params))).unwrap();
+            assert!(mirrored.data == full.data, "{view:?}");
+        }
+    }
+
+
+    #[test]
+    fn mirrored_render_matches_full_render_for_every_pattern() {
+        for pattern in ["grid", "jittered", "rotated-grid"] {
+            let params = params_for("cpu-par-iter", &["--samples", "16", "--sample-pattern", pattern], ["-2.2", "0.8", "-1.5", "1.5"]);
+            assert_eq!(find_mirror(&params).is_some(), pattern == "grid", "{pattern}");
+
+            let full = crate::backends::cpu::run_cpu_par_iter(&params);
+            let mirrored = render(&params, |params| Ok(crate::backends::cpu::run_cpu_par_iter(params))).unwrap();
+            assert!(mirrored.data == full.data, "{pattern}");
+        }
+    }
+
+
+    #[test]
+    fn asymmetric_views_are_not_mirrored() {
+        // real axis outside of the view and between two rows
+        assert!(find_mirror(&params_for("cpu-par-iter", &[], ["-2.2", "0.8", "0.1", "1.5"])).is_none());
+        assert!(find_mirror(&params_for("cpu-par-iter", &[], ["-2.2", "0.8", "-1.51", "1.5"])).is_none());
+        assert!(find_mirror(&params_for("hybrid", &["--hybrid-gpu", "opengl-glsl"], ["-2.2", "0.8", "-1.5", "1.5"])).is_none());
+    }
+
+
+    #[test]
+    fn shader_rows_are_checked_in_band_arithmetic() {
+        // the band starts at the axis, so its limits are rounded to f32 differently than the ones of the whole view
+        let view = ["-2.2", "0.8", "-1.1", "1.1"];
+        assert!(find_mirror(&params_for("cpu-par-iter", &[], view)).is_some());
+        assert!(find_mirror(&params_for("opengl-glsl", &[], view)).is_none());
+    }
+}